@@ -0,0 +1,44 @@
+//! Provides code actions
+//! The diagnostics of proof worksheets come with quick fixes, like adding placeholders
+//! for missing hypotheses, or replacing unknown labels and mismatched formulas.
+
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::Database;
+use std::collections::HashMap;
+
+pub(crate) fn code_actions(
+    path: FileRef,
+    range: Range,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Option<CodeActionResponse>, ServerError> {
+    let uri = path.url().clone();
+    let worksheet = match vfs.source(path, &db)? {
+        FileContents::MMPFile(worksheet) => worksheet,
+        FileContents::MMFile(_) => return Ok(None),
+    };
+    Ok(Some(
+        worksheet
+            .quick_fixes(range)
+            .into_iter()
+            .map(|fix| {
+                let mut changes = HashMap::new();
+                changes.insert(uri.clone(), vec![fix.edit]);
+                CodeActionOrCommand::CodeAction(CodeAction {
+                    title: fix.title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![fix.diagnostic]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(changes),
+                        ..WorkspaceEdit::default()
+                    }),
+                    ..CodeAction::default()
+                })
+            })
+            .collect(),
+    ))
+}
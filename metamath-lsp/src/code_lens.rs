@@ -0,0 +1,231 @@
+//! Provides code lenses
+//! Above each theorem and axiom of a Metamath database file, lenses show how many
+//! theorems directly use it, how many steps its proof has, and whether it depends
+//! on a discouraged axiom.
+
+use crate::definition::SourceLocations;
+use crate::references::label_span;
+use crate::references::proof_labels;
+use crate::server::Cancellation;
+use crate::util::FileRef;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use memchr::memmem;
+use metamath_knife::statement::as_str;
+use metamath_knife::statement::StatementRef;
+use metamath_knife::Database;
+use metamath_knife::StatementType;
+use serde_json::to_value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The marker used in the comments of axioms which shall not be used in new proofs
+const DISCOURAGED_MARKER: &[u8] = b"(New usage is discouraged.)";
+
+/// Whether the comment of an axiom marks it as discouraged
+fn is_discouraged(stmt: &StatementRef<'_>) -> bool {
+    stmt.associated_comment().map_or(false, |comment| {
+        let buf = &comment.segment().segment.buffer;
+        // The marker may be split over several lines
+        let text = comment
+            .span()
+            .as_ref(buf)
+            .split(u8::is_ascii_whitespace)
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(&b' ');
+        memmem::find(&text, DISCOURAGED_MARKER).is_some()
+    })
+}
+
+/// The number of steps of the proof of a statement, or `None` if the proof is incomplete,
+/// i.e. if it contains unknown `?` steps.
+/// For compressed proofs, each step is encoded by a number ending with a letter from `A` to `T`.
+fn proof_steps(stmt: &StatementRef<'_>) -> Option<usize> {
+    let len = stmt.proof_len();
+    if len > 0 && stmt.proof_slice_at(0) == b"(" {
+        let mut steps = 0;
+        for token in (0..len)
+            .map(|i| stmt.proof_slice_at(i))
+            .skip_while(|token| *token != b")")
+        {
+            if token.contains(&b'?') {
+                return None;
+            }
+            steps += token
+                .iter()
+                .filter(|&&c| (b'A'..=b'T').contains(&c))
+                .count();
+        }
+        Some(steps)
+    } else if (0..len).any(|i| stmt.proof_slice_at(i) == b"?") {
+        None
+    } else {
+        Some(len)
+    }
+}
+
+/// Usage information collected over the whole database.
+/// It is kept by the server until the database is parsed again.
+#[derive(Debug, Default)]
+pub(crate) struct Usages {
+    /// The number of theorems using each label in their proof
+    counts: HashMap<Vec<u8>, usize>,
+    /// For each statement depending on a discouraged axiom, one of those axioms
+    discouraged: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Usages {
+    /// Collects the usages in a single pass over the database.
+    /// Since proofs only refer to previous statements, the dependencies on discouraged
+    /// axioms are known for all the labels used by a proof when it is visited.
+    pub(crate) fn new(db: &Database, cancel: &Cancellation) -> Result<Self, ServerError> {
+        let mut usages = Self::default();
+        for stmt in db.statements() {
+            cancel.check()?;
+            match stmt.statement_type() {
+                StatementType::Axiom if is_discouraged(&stmt) => {
+                    usages
+                        .discouraged
+                        .insert(stmt.label().to_vec(), stmt.label().to_vec());
+                }
+                StatementType::Provable => {
+                    let labels: HashSet<_> = proof_labels(&stmt)
+                        .into_iter()
+                        .map(|(_, label)| label)
+                        .collect();
+                    let mut axiom = None;
+                    for label in labels {
+                        *usages.counts.entry(label.to_vec()).or_default() += 1;
+                        axiom = axiom.or_else(|| usages.discouraged.get(label).cloned());
+                    }
+                    if let Some(axiom) = axiom {
+                        usages.discouraged.insert(stmt.label().to_vec(), axiom);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(usages)
+    }
+}
+
+/// Builds the lenses for a single statement, placed at the given range
+fn statement_lenses(
+    stmt: &StatementRef<'_>,
+    range: Range,
+    uri: &Url,
+    usages: &Usages,
+    sources: &mut SourceLocations<'_>,
+    db: &Database,
+) -> Result<Vec<CodeLens>, ServerError> {
+    let label = as_str(stmt.label());
+    let count = usages.counts.get(stmt.label()).copied().unwrap_or_default();
+    let mut lenses = vec![CodeLens {
+        range,
+        command: Some(Command {
+            title: match count {
+                1 => "1 usage".to_string(),
+                _ => format!("{} usages", count),
+            },
+            command: "metamath.showReferences".to_string(),
+            arguments: Some(vec![to_value(uri)?, to_value(range.start)?]),
+        }),
+        data: None,
+    }];
+    if stmt.statement_type() == StatementType::Provable {
+        lenses.push(CodeLens {
+            range,
+            command: Some(Command {
+                title: match proof_steps(stmt) {
+                    None => "incomplete proof".to_string(),
+                    Some(1) => "1 proof step".to_string(),
+                    Some(steps) => format!("{} proof steps", steps),
+                },
+                command: "metamath.showProof".to_string(),
+                arguments: Some(vec![to_value(label)?]),
+            }),
+            data: None,
+        });
+    }
+    if let Some(axiom) = usages.discouraged.get(stmt.label()) {
+        let title = if axiom == stmt.label() {
+            "discouraged".to_string()
+        } else {
+            format!("depends on discouraged {}", as_str(axiom))
+        };
+        // Leads to the discouraged axiom
+        let location = db
+            .statement(axiom)
+            .and_then(|axiom_stmt| sources.location(axiom_stmt, label_span(&axiom_stmt)?));
+        lenses.push(CodeLens {
+            range,
+            command: Some(Command {
+                title,
+                command: "metamath.showLocation".to_string(),
+                arguments: match location {
+                    Some(location) => {
+                        Some(vec![to_value(location.uri)?, to_value(location.range)?])
+                    }
+                    None => None,
+                },
+            }),
+            data: None,
+        });
+    }
+    Ok(lenses)
+}
+
+/// Provides the code lenses of a database file.
+/// The usages are only requested for database files, since they are collected over the whole database.
+pub(crate) fn code_lens(
+    path: FileRef,
+    vfs: &Vfs,
+    db: Database,
+    usages: impl FnOnce() -> Result<Arc<Usages>, ServerError>,
+    cancel: &Cancellation,
+) -> Result<Option<Vec<CodeLens>>, ServerError> {
+    if !path.has_extension("mm") {
+        return Ok(None);
+    }
+    let source = vfs.source(path.clone(), &db)?;
+    let usages = usages()?;
+    let mut sources = SourceLocations::new(vfs, &db);
+    // Remember which source files are the requested one, to avoid comparing paths
+    // for each statement
+    let mut is_in_file = HashMap::new();
+    let mut lenses = vec![];
+    for stmt in db.statements() {
+        cancel.check()?;
+        if !matches!(
+            stmt.statement_type(),
+            StatementType::Axiom | StatementType::Provable
+        ) {
+            continue;
+        }
+        let name = db.statement_source_name(stmt.address());
+        if !*is_in_file
+            .entry(name)
+            .or_insert_with(|| path.is_named(name))
+        {
+            continue;
+        }
+        if let Some(span) = label_span(&stmt) {
+            let range = Range::new(
+                source.byte_to_lsp_position(span.start as usize),
+                source.byte_to_lsp_position(span.end as usize),
+            );
+            lenses.extend(statement_lenses(
+                &stmt,
+                range,
+                path.url(),
+                &usages,
+                &mut sources,
+                &db,
+            )?);
+        }
+    }
+    Ok(Some(lenses))
+}
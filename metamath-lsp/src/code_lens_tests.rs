@@ -0,0 +1,61 @@
+use crate::code_lens::code_lens;
+use crate::code_lens::Usages;
+use crate::server::Cancellation;
+use crate::test_fixtures::{open_db, TEST_DB};
+use lsp_types::*;
+use std::sync::Arc;
+
+#[test]
+fn code_lenses_of_database() {
+    let text = format!(
+        "{}$( (New usage is discouraged.) $)\nax-2 $a |- ( ph -> ph ) $.\nth1 $p |- ( ps -> ( ph -> ph ) ) $= ax-2 ax-1 ax-mp $.\nth2 $p wff ph $= wph $.\n",
+        TEST_DB
+    );
+    let (path, vfs, db) = open_db("code_lens.mm", &text);
+    let cancel = Cancellation::default();
+    let usages = Arc::new(Usages::new(&db, &cancel).unwrap());
+    let lenses = code_lens(path, &vfs, db.clone(), || Ok(usages), &cancel)
+        .unwrap()
+        .unwrap();
+    let summary: Vec<_> = lenses
+        .iter()
+        .map(|lens| {
+            let command = lens.command.as_ref().unwrap();
+            (
+                lens.range.start.line,
+                command.title.as_str(),
+                command.command.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (4, "0 usages", "metamath.showReferences"),
+            (8, "1 usage", "metamath.showReferences"),
+            (10, "1 usage", "metamath.showReferences"),
+            (13, "0 usages", "metamath.showReferences"),
+            (13, "incomplete proof", "metamath.showProof"),
+            (16, "1 usage", "metamath.showReferences"),
+            (16, "discouraged", "metamath.showLocation"),
+            (17, "0 usages", "metamath.showReferences"),
+            (17, "3 proof steps", "metamath.showProof"),
+            (17, "depends on discouraged ax-2", "metamath.showLocation"),
+            (18, "0 usages", "metamath.showReferences"),
+            (18, "1 proof step", "metamath.showProof"),
+        ]
+    );
+    // The discouraged lenses lead to the label of the axiom
+    let arguments = lenses[9]
+        .command
+        .as_ref()
+        .unwrap()
+        .arguments
+        .clone()
+        .unwrap();
+    let range: Range = serde_json::from_value(arguments[1].clone()).unwrap();
+    assert_eq!(
+        range,
+        Range::new(Position::new(16, 0), Position::new(16, 4))
+    );
+}
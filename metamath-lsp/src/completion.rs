@@ -0,0 +1,317 @@
+//! Provides completion for labels and math tokens
+
+use crate::definition::find_statement;
+use crate::hover::comment_markup_format;
+use crate::rope_ext::utf16_to_byte_idx;
+use crate::typesetting::Typesetting;
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::statement::as_str;
+use metamath_knife::statement::StatementAddress;
+use metamath_knife::Database;
+use metamath_knife::StatementRef;
+use metamath_knife::StatementType;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::ops::Bound;
+
+/// Maximum number of completion items returned at once.
+/// Since the list is then marked as incomplete, the client asks again as the user types.
+const MAX_COMPLETION_ITEMS: usize = 200;
+
+/// What kind of token is expected at the completion position
+enum CompletionContext {
+    /// A statement label, e.g. in a comment or in the label field of a proof step
+    Label,
+    /// A label in the proof of a theorem in a database file, with the label of that theorem if found
+    ProofLabel(Option<String>),
+    /// A hypothesis label, for the label field of a hypothesis proof step
+    Hypothesis,
+    /// A math token, e.g. in a statement or a proof step formula
+    MathToken,
+}
+
+/// Check whether a character separates tokens in the context of completion
+fn is_separator(c: char) -> bool {
+    c.is_ascii_whitespace() || c == ':' || c == ','
+}
+
+/// Returns the part of the token currently being typed, before the cursor
+fn prefix_at(line: &str, character: u32) -> &str {
+    let before = &line[..utf16_to_byte_idx(line, character)];
+    let start = before.rfind(is_separator).map_or(0, |idx| idx + 1);
+    &before[start..]
+}
+
+/// Determines the completion context in a Metamath database file,
+/// based on the last keyword found before the cursor.
+fn mm_context(source: &FileContents, pos: Position) -> Option<CompletionContext> {
+    const MAX_LOOKBACK_LINES: u32 = 200;
+    let first_line = pos.line.saturating_sub(MAX_LOOKBACK_LINES);
+    let line = source.line(pos.line);
+    let before = &line[..utf16_to_byte_idx(&line, pos.character)];
+    let mut current_tokens: Vec<&str> = before.split_ascii_whitespace().collect();
+    if !before.ends_with(|c: char| c.is_ascii_whitespace()) {
+        // Skip the token being typed
+        current_tokens.pop();
+    }
+    if current_tokens.last() == Some(&"~") {
+        return Some(CompletionContext::Label);
+    }
+    let previous_tokens = (first_line..pos.line).rev().flat_map(|line_idx| {
+        source
+            .line(line_idx)
+            .split_ascii_whitespace()
+            .rev()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    });
+    let mut tokens = current_tokens
+        .into_iter()
+        .rev()
+        .map(str::to_string)
+        .chain(previous_tokens);
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "$=" => {
+                // The label of the theorem comes right before its `$p` keyword
+                let theorem = tokens
+                    .find(|token| token == "$p")
+                    .and_then(|_| tokens.next());
+                return Some(CompletionContext::ProofLabel(theorem));
+            }
+            "$a" | "$p" | "$e" | "$f" | "$c" | "$v" | "$d" => {
+                return Some(CompletionContext::MathToken)
+            }
+            "$." | "$(" | "$)" | "${" | "$}" | "$[" | "$]" => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Determines the completion context in a proof worksheet,
+/// based on the field of the step line where the cursor is.
+fn mmp_context(line: &str, pos: Position) -> Option<CompletionContext> {
+    if line.starts_with(|c: char| c == ' ' || c == '\t') {
+        // Follow-up line of a step: this is part of the formula
+        return Some(CompletionContext::MathToken);
+    }
+    if line.is_empty() || line.starts_with('*') || line.starts_with('$') {
+        return None;
+    }
+    let before = &line[..utf16_to_byte_idx(line, pos.character)];
+    let mut fields = before.splitn(3, ':');
+    let name = fields.next()?;
+    let _hyps = fields.next()?;
+    let label_and_formula = fields.next()?;
+    if label_and_formula.contains(|c: char| c.is_ascii_whitespace()) {
+        Some(CompletionContext::MathToken)
+    } else if name.starts_with('h') {
+        Some(CompletionContext::Hypothesis)
+    } else {
+        Some(CompletionContext::Label)
+    }
+}
+
+/// The completion item kind corresponding to a statement type
+fn completion_kind(stmt: &StatementRef<'_>) -> CompletionItemKind {
+    match stmt.statement_type() {
+        StatementType::Provable => CompletionItemKind::FUNCTION,
+        StatementType::Axiom if stmt.label().starts_with(b"df-") => CompletionItemKind::CONSTRUCTOR,
+        StatementType::Axiom => CompletionItemKind::INTERFACE,
+        StatementType::Essential | StatementType::Floating => CompletionItemKind::FIELD,
+        _ => CompletionItemKind::TEXT,
+    }
+}
+
+/// Builds a completion item for the given label or math token.
+/// The token itself is stored as data, so that the item can later be resolved.
+fn completion_item(name: &str, kind: CompletionItemKind) -> CompletionItem {
+    CompletionItem {
+        label: name.to_string(),
+        kind: Some(kind),
+        data: Some(Value::String(name.to_string())),
+        ..CompletionItem::default()
+    }
+}
+
+/// Lists the assertion labels starting with the given prefix, up to the given bound,
+/// and accepted by the given filter.
+fn label_items(
+    prefix: &str,
+    end: Bound<StatementAddress>,
+    db: &Database,
+    filter: impl Fn(StatementRef<'_>) -> bool,
+) -> Vec<CompletionItem> {
+    db.statements_range_address((Bound::Unbounded, end))
+        .filter(|stmt| {
+            stmt.statement_type().is_assertion()
+                && stmt.label().starts_with(prefix.as_bytes())
+                && filter(*stmt)
+        })
+        .take(MAX_COMPLETION_ITEMS)
+        .map(|stmt| completion_item(as_str(stmt.label()), completion_kind(&stmt)))
+        .collect()
+}
+
+/// Lists the essential hypotheses of the given theorem starting with the given prefix.
+fn hypothesis_items(
+    prefix: &str,
+    sadd: Option<StatementAddress>,
+    db: &Database,
+) -> Vec<CompletionItem> {
+    let sadd = match sadd {
+        Some(sadd) => sadd,
+        None => return vec![],
+    };
+    let stmt = db.statement_by_address(sadd);
+    match db.scope_result().get(stmt.label()) {
+        Some(frame) => frame
+            .as_ref(db)
+            .essentials()
+            .filter_map(|(label, _)| db.statement_by_label(label))
+            .filter(|hyp| hyp.label().starts_with(prefix.as_bytes()))
+            .map(|hyp| completion_item(as_str(hyp.label()), CompletionItemKind::FIELD))
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Lists the floating and essential hypotheses in scope before the given statement,
+/// starting with the given prefix.
+/// Hypotheses declared in a block are dropped when that block is closed.
+fn scoped_hypothesis_items(
+    prefix: &str,
+    sadd: StatementAddress,
+    db: &Database,
+) -> Vec<CompletionItem> {
+    let mut scopes = vec![vec![]];
+    for stmt in db.statements_range_address((Bound::Unbounded, Bound::Excluded(sadd))) {
+        match stmt.statement_type() {
+            StatementType::OpenGroup => scopes.push(vec![]),
+            StatementType::CloseGroup if scopes.len() > 1 => {
+                scopes.pop();
+            }
+            StatementType::Essential | StatementType::Floating
+                if stmt.label().starts_with(prefix.as_bytes()) =>
+            {
+                if let Some(scope) = scopes.last_mut() {
+                    scope.push(completion_item(
+                        as_str(stmt.label()),
+                        completion_kind(&stmt),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    scopes.into_iter().flatten().collect()
+}
+
+/// Lists the math constants and variables starting with the given prefix, up to the given bound.
+fn math_token_items(
+    prefix: &str,
+    end: Bound<StatementAddress>,
+    db: &Database,
+) -> Vec<CompletionItem> {
+    let mut seen = HashSet::new();
+    let mut items = vec![];
+    for stmt in db.statements_range_address((Bound::Unbounded, end)) {
+        let kind = match stmt.statement_type() {
+            StatementType::Constant => CompletionItemKind::CONSTANT,
+            StatementType::Variable => CompletionItemKind::VARIABLE,
+            _ => continue,
+        };
+        for token in stmt.math_iter() {
+            if token.slice.starts_with(prefix.as_bytes()) && seen.insert(token.slice) {
+                items.push(completion_item(as_str(token.slice), kind));
+                if items.len() >= MAX_COMPLETION_ITEMS {
+                    return items;
+                }
+            }
+        }
+    }
+    items
+}
+
+pub(crate) fn completion(
+    path: FileRef,
+    pos: Position,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Option<CompletionResponse>, ServerError> {
+    let source = vfs.source(path, &db)?;
+    let line = source.line(pos.line);
+    let prefix = prefix_at(&line, pos.character);
+    let items = match &source {
+        FileContents::MMFile(_) => match mm_context(&source, pos) {
+            Some(CompletionContext::MathToken) => math_token_items(prefix, Bound::Unbounded, &db),
+            Some(CompletionContext::ProofLabel(theorem)) => {
+                // Only the statements before the theorem, and its hypotheses, may be used in its proof
+                match theorem.and_then(|label| db.statement(label.as_bytes())) {
+                    Some(stmt) => {
+                        let mut items = scoped_hypothesis_items(prefix, stmt.address(), &db);
+                        items.extend(label_items(
+                            prefix,
+                            Bound::Excluded(stmt.address()),
+                            &db,
+                            |_| true,
+                        ));
+                        items.truncate(MAX_COMPLETION_ITEMS);
+                        items
+                    }
+                    None => label_items(prefix, Bound::Unbounded, &db, |_| true),
+                }
+            }
+            Some(_) => label_items(prefix, Bound::Unbounded, &db, |_| true),
+            None => return Ok(None),
+        },
+        FileContents::MMPFile(worksheet) => match mmp_context(&line, pos) {
+            Some(CompletionContext::Label) => {
+                // Only propose the assertions which can be applied to derive the current step
+                let step = worksheet
+                    .step_at(pos)
+                    .0
+                    .map(|step_idx| &worksheet.steps[step_idx].step);
+                label_items(prefix, worksheet.scope_end(), &db, |stmt| {
+                    step.map_or(true, |step| step.can_apply(stmt, &db))
+                })
+            }
+            Some(CompletionContext::Hypothesis) => hypothesis_items(prefix, worksheet.sadd, &db),
+            Some(CompletionContext::MathToken) => {
+                math_token_items(prefix, worksheet.scope_end(), &db)
+            }
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(CompletionResponse::List(CompletionList {
+        is_incomplete: items.len() >= MAX_COMPLETION_ITEMS,
+        items,
+    })))
+}
+
+pub(crate) fn completion_resolve(
+    mut item: CompletionItem,
+    db: Database,
+    typesetting: Option<&Typesetting>,
+) -> Result<CompletionItem, ServerError> {
+    if let Some(Value::String(token)) = &item.data {
+        if let Some(stmt) = find_statement(token.as_bytes(), &db) {
+            let mut detail = String::new();
+            for token in stmt.math_iter() {
+                detail.push_str(as_str(&token));
+                detail.push(' ');
+            }
+            item.detail = Some(detail.trim_end().to_string());
+            item.documentation = Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: comment_markup_format(stmt, &db, typesetting)?,
+            }));
+        }
+    }
+    Ok(item)
+}
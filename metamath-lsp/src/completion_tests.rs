@@ -0,0 +1,62 @@
+use crate::completion::completion;
+use crate::test_fixtures::{open_db, TEST_DB};
+use lsp_types::*;
+
+/// The labels of the items of a completion response
+fn completion_labels(response: Option<CompletionResponse>) -> Vec<String> {
+    match response {
+        Some(CompletionResponse::List(list)) => {
+            list.items.into_iter().map(|item| item.label).collect()
+        }
+        Some(CompletionResponse::Array(items)) => {
+            items.into_iter().map(|item| item.label).collect()
+        }
+        None => vec![],
+    }
+}
+
+#[test]
+fn completion_labels_in_proof() {
+    let text = format!("{}th1 $p |- ph $= ax", TEST_DB);
+    let (path, vfs, db) = open_db("completion_labels.mm", &text);
+    let pos = Position::new(15, 19);
+    let response = completion(path, pos, &vfs, db).unwrap();
+    assert_eq!(completion_labels(response), vec!["ax-mp", "ax-1"]);
+}
+
+#[test]
+fn completion_math_tokens() {
+    let text = format!("{}th1 $p |- ( ph", TEST_DB);
+    let (path, vfs, db) = open_db("completion_math.mm", &text);
+    let pos = Position::new(15, 15);
+    let response = completion(path, pos, &vfs, db).unwrap();
+    assert_eq!(completion_labels(response), vec!["ph"]);
+}
+
+#[test]
+fn completion_after_non_ascii_characters() {
+    // The position is given in UTF-16 code units, here `é` is one unit but two bytes
+    let text = format!("{}$( é $) th1 $p |- ph $= ax", TEST_DB);
+    let (path, vfs, db) = open_db("completion_non_ascii.mm", &text);
+    let response = completion(path.clone(), Position::new(15, 27), &vfs, db.clone()).unwrap();
+    assert_eq!(completion_labels(response), vec!["ax-mp", "ax-1"]);
+    // Within the comment, right after the non-ASCII character
+    let response = completion(path, Position::new(15, 4), &vfs, db).unwrap();
+    assert!(response.is_none());
+}
+
+#[test]
+fn completion_hypotheses_in_proof() {
+    // Only the hypotheses in scope are proposed, and only the assertions before the theorem
+    let text = format!(
+        "{}${{\n    th1.1 $e |- ps $.\n    th1 $p |- ph $= \n$}}\nth2 $a |- ps $.\n",
+        TEST_DB
+    );
+    let (path, vfs, db) = open_db("completion_hypotheses.mm", &text);
+    let pos = Position::new(17, 20);
+    let response = completion(path, pos, &vfs, db).unwrap();
+    assert_eq!(
+        completion_labels(response),
+        vec!["wph", "wps", "th1.1", "wi", "ax-mp", "ax-1", "a1i"]
+    );
+}
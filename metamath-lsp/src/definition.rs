@@ -1,17 +1,59 @@
 //! Provides hover information
 
+use crate::proof::ProofWorksheet;
 use crate::server::word_at;
 use crate::util::FileRef;
+use crate::vfs::FileContents;
 use crate::vfs::Vfs;
 use crate::ServerError;
 use lsp_types::*;
+use metamath_knife::formula::Label;
 use metamath_knife::grammar::FormulaToken;
+use metamath_knife::nameck::Atom;
 use metamath_knife::Database;
 use metamath_knife::Span;
 use metamath_knife::StatementRef;
 use metamath_knife::StatementType;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+/// Finds the syntax axiom (or floating hypothesis) for a math symbol,
+/// by parsing that symbol alone as a formula.
+/// Returns `None` for symbols which cannot form a formula by themselves, like `(`.
+pub(crate) fn syntax_axiom(symbol: Atom, db: &Database) -> Option<Label> {
+    // TODO - this could be provided as a utility by metamath-knife wihtout having to build a formula
+    let grammar = db.grammar_result();
+    let formula = grammar
+        .parse_formula(
+            &mut [FormulaToken {
+                symbol,
+                span: Span::default(),
+            }]
+            .into_iter(),
+            &grammar.typecodes(),
+            false,
+            db.name_result(),
+        )
+        .ok()?;
+    formula.get_by_path(&[])
+}
+
+/// Finds the `df-` definition of a syntax axiom,
+/// i.e. the definition whose left-hand side is built by that syntax axiom.
+pub(crate) fn find_definition(syntax_label: Label, db: &Database) -> Option<StatementRef<'_>> {
+    let provider = db.stmt_parse_result();
+    db.statements()
+        .filter(|stmt| {
+            stmt.statement_type() == StatementType::Axiom && stmt.label().starts_with(b"df-")
+        })
+        .find(|stmt| {
+            provider
+                .get_formula(stmt)
+                .and_then(|formula| formula.get_by_path(&[0]))
+                == Some(syntax_label)
+        })
+}
+
 /// Finds the statement to display for a given token or label
 pub(crate) fn find_statement<'a>(token: &'a [u8], db: &'a Database) -> Option<StatementRef<'a>> {
     let nset = db.name_result();
@@ -23,21 +65,9 @@ pub(crate) fn find_statement<'a>(token: &'a [u8], db: &'a Database) -> Option<St
         let stmt = db.statement_by_address(symbol.address.statement);
         match stmt.statement_type() {
             StatementType::Constant | StatementType::Variable => {
-                // TODO - this could be provided as a utility by metamath-knife wihtout having to build a formula
-                let grammar = db.grammar_result();
-                if let Ok(formula) = grammar.parse_formula(
-                    &mut [FormulaToken {
-                        symbol: symbol.atom,
-                        span: Span::default(),
-                    }]
-                    .into_iter(),
-                    &grammar.typecodes(),
-                    false,
-                    nset,
-                ) {
-                    db.statement(nset.atom_name(formula.get_by_path(&[])?))
-                } else {
-                    Some(stmt)
+                match syntax_axiom(symbol.atom, db) {
+                    Some(label) => db.statement(nset.atom_name(label)),
+                    None => Some(stmt),
                 }
             }
             _ => Some(stmt),
@@ -66,15 +96,118 @@ pub(crate) fn span_range(
 }
 
 pub(crate) fn stmt_location(stmt: StatementRef<'_>, vfs: &Vfs, db: &Database) -> Option<Location> {
-    let path: PathBuf = db.statement_source_name(stmt.address()).into();
-    let source = vfs.source(path.clone().into(), db).ok()?;
-    let uri = Url::from_file_path(path.canonicalize().ok()?).ok()?;
-    let span = stmt.span();
-    let range = Range::new(
-        source.byte_to_lsp_position(span.start as usize),
-        source.byte_to_lsp_position(span.end as usize),
-    );
-    Some(Location { uri, range })
+    SourceLocations::new(vfs, db).location(stmt, stmt.span())
+}
+
+/// Provides the locations of spans within the database source files.
+/// Each source file is only loaded, and its URL built, once.
+pub(crate) struct SourceLocations<'a> {
+    vfs: &'a Vfs,
+    db: &'a Database,
+    /// The URL and contents of each source file, by file name
+    sources: HashMap<String, Option<(Url, FileContents)>>,
+}
+
+impl<'a> SourceLocations<'a> {
+    pub(crate) fn new(vfs: &'a Vfs, db: &'a Database) -> Self {
+        Self {
+            vfs,
+            db,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Loads the source file with the given name, along with its URL.
+    /// Returns `None` if the file cannot be found.
+    fn load(&self, name: &str) -> Option<(Url, FileContents)> {
+        let path = PathBuf::from(name).canonicalize().ok()?;
+        let uri = Url::from_file_path(path).ok()?;
+        let source = self.vfs.source(FileRef::from(uri.clone()), self.db).ok()?;
+        Some((uri, source))
+    }
+
+    /// The location of a span within the source of the given statement
+    pub(crate) fn location(&mut self, stmt: StatementRef<'_>, span: Span) -> Option<Location> {
+        let name = self.db.statement_source_name(stmt.address());
+        if !self.sources.contains_key(name) {
+            let source = self.load(name);
+            self.sources.insert(name.to_string(), source);
+        }
+        let (uri, source) = self.sources.get(name)?.as_ref()?;
+        let range = Range::new(
+            source.byte_to_lsp_position(span.start as usize),
+            source.byte_to_lsp_position(span.end as usize),
+        );
+        Some(Location {
+            uri: uri.clone(),
+            range,
+        })
+    }
+}
+
+/// Finds the `$e` hypothesis of the theorem corresponding to a hypothesis step of a proof worksheet,
+/// either by its label, or by the position of the step among the hypothesis steps.
+fn hyp_statement(worksheet: &ProofWorksheet, step_idx: usize) -> Option<StatementRef<'_>> {
+    let db = &worksheet.db;
+    if let Some(stmt) = db.statement(worksheet.step_label(step_idx)) {
+        if stmt.statement_type() == StatementType::Essential {
+            return Some(stmt);
+        }
+    }
+    let hyp_idx = worksheet.steps[..step_idx]
+        .iter()
+        .filter(|step_info| step_info.step.is_hyp())
+        .count();
+    let theorem = db.statement_by_address(worksheet.sadd?);
+    let (label, _) = db
+        .scope_result()
+        .get(theorem.label())?
+        .as_ref(db)
+        .essentials()
+        .nth(hyp_idx)?;
+    db.statement_by_label(label)
+}
+
+/// Finds the definition of the item at the given position of a proof worksheet:
+/// - for a hypothesis reference, the step it refers to,
+/// - for the name of a hypothesis step, the corresponding `$e` hypothesis in the database,
+/// - for the `THEOREM=` and `LOC_AFTER=` labels of the header, the corresponding statements.
+///
+/// Returns `None` if there is no such item at that position.
+fn worksheet_definition(
+    uri: &Url,
+    worksheet: &ProofWorksheet,
+    pos: Position,
+    vfs: &Vfs,
+    db: &Database,
+) -> Option<Location> {
+    if let Some(label) = worksheet.header_label_at(pos) {
+        return stmt_location(db.statement(label.as_bytes())?, vfs, db);
+    }
+    let (step_idx, offset) = worksheet.step_at(pos);
+    let step_idx = step_idx?;
+    let step_info = &worksheet.steps[step_idx];
+    // The cursor may be just after the last character of a token
+    let contains = |range: std::ops::Range<usize>| range.start <= offset && offset <= range.end;
+    if let Some(hyp_span) = step_info
+        .step
+        .hyps()
+        .find(|span| contains(span.as_range(0)))
+    {
+        let &hyp_step_idx = worksheet
+            .steps_by_name
+            .get(hyp_span.as_ref(&step_info.source))?;
+        return Some(Location {
+            uri: uri.clone(),
+            range: worksheet
+                .span_range(hyp_step_idx, worksheet.steps[hyp_step_idx].step.name_span()),
+        });
+    }
+    // This includes the `h` prefix of the step name
+    if step_info.step.is_hyp() && offset <= step_info.step.name_span().as_range(0).end {
+        return stmt_location(hyp_statement(worksheet, step_idx)?, vfs, db);
+    }
+    None
 }
 
 pub(crate) fn definition(
@@ -83,7 +216,13 @@ pub(crate) fn definition(
     vfs: &Vfs,
     db: Database,
 ) -> Result<Option<Location>, ServerError> {
+    let uri = path.url().clone();
     let text = vfs.source(path, &db)?;
+    if let FileContents::MMPFile(worksheet) = &text {
+        if let Some(location) = worksheet_definition(&uri, worksheet, pos, vfs, &db) {
+            return Ok(Some(location));
+        }
+    }
     let (word, _) = word_at(pos, text);
     if let Some(stmt) = find_statement(word.as_bytes(), &db) {
         Ok(stmt_location(stmt, vfs, &db))
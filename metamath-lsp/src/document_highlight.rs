@@ -0,0 +1,283 @@
+//! Provides document highlights
+//! In Metamath database files, the declaration and uses of the label under the cursor
+//! are highlighted, or the occurrences of the math token under the cursor within
+//! its enclosing block or statement. In proof worksheets, step names are highlighted together with
+//! the hypothesis references to them.
+
+use crate::proof::ProofWorksheet;
+use crate::references::{comment_uses, label_span, proof_uses};
+use crate::rope_ext::RopeExt;
+use crate::server::word_at;
+use crate::server::Cancellation;
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use memchr::memmem;
+use metamath_knife::Database;
+use metamath_knife::Span;
+use metamath_knife::StatementRef;
+use std::collections::HashMap;
+use xi_rope::Rope;
+
+/// Maximum number of highlights returned for a Metamath database file
+const MAX_HIGHLIGHTS: usize = 1000;
+
+/// Maximum number of bytes searched before and after the cursor
+/// for the block or statement enclosing a math token
+const MAX_SCOPE_BYTES: usize = 64 * 1024;
+
+/// The highlight of a span of a Metamath database file
+fn span_highlight(text: &Rope, span: Span, kind: DocumentHighlightKind) -> DocumentHighlight {
+    DocumentHighlight {
+        range: Range::new(
+            text.byte_to_lsp_position(span.start as usize),
+            text.byte_to_lsp_position(span.end as usize),
+        ),
+        kind: Some(kind),
+    }
+}
+
+/// Highlights the declaration of a label, and its uses in the proofs and comments
+/// of the statements of the given Metamath database file
+fn label_highlights(
+    stmt: StatementRef<'_>,
+    path: &FileRef,
+    text: &Rope,
+    db: &Database,
+    cancel: &Cancellation,
+) -> Result<Vec<DocumentHighlight>, ServerError> {
+    let label = stmt.label();
+    // Remember which source files are the requested one, to avoid comparing paths
+    // for each statement
+    let mut is_in_file = HashMap::new();
+    let mut highlights = vec![];
+    for use_stmt in db.statements() {
+        cancel.check()?;
+        let name = db.statement_source_name(use_stmt.address());
+        if !*is_in_file
+            .entry(name)
+            .or_insert_with(|| path.is_named(name))
+        {
+            continue;
+        }
+        if use_stmt.address() == stmt.address() {
+            if let Some(span) = label_span(&use_stmt) {
+                highlights.push(span_highlight(text, span, DocumentHighlightKind::WRITE));
+            }
+        }
+        for span in proof_uses(&use_stmt, label)
+            .into_iter()
+            .chain(comment_uses(&use_stmt, label))
+        {
+            highlights.push(span_highlight(text, span, DocumentHighlightKind::READ));
+        }
+        if highlights.len() >= MAX_HIGHLIGHTS {
+            highlights.truncate(MAX_HIGHLIGHTS);
+            break;
+        }
+    }
+    Ok(highlights)
+}
+
+/// The byte range of the `${ $}` block enclosing the given position,
+/// or of the statement enclosing it if it is not in a block.
+/// Only up to [`MAX_SCOPE_BYTES`] are searched on each side.
+fn enclosing_scope(text: &Rope, cursor: usize) -> std::ops::Range<usize> {
+    let window_start = cursor.saturating_sub(MAX_SCOPE_BYTES);
+    let window_end = text.len().min(cursor + MAX_SCOPE_BYTES);
+    let before = text.cow_for_range(window_start..cursor);
+    let after = text.cow_for_range(cursor..window_end);
+    let offset = |slice: &str, token: &str| token.as_ptr() as usize - slice.as_ptr() as usize;
+    // Look backwards for the start of the enclosing block, skipping the nested blocks,
+    // and for the end of the previous statement
+    let mut depth = 0;
+    let mut in_block = false;
+    let mut statement_start = None;
+    let mut start = window_start;
+    for token in before.split_ascii_whitespace().rev() {
+        let token_end = window_start + offset(&before, token) + token.len();
+        match token {
+            "${" if depth == 0 => {
+                in_block = true;
+                start = token_end;
+                break;
+            }
+            "${" => depth -= 1,
+            "$}" => {
+                statement_start.get_or_insert(token_end);
+                depth += 1;
+            }
+            "$." | "$)" => {
+                statement_start.get_or_insert(token_end);
+            }
+            _ => {}
+        }
+    }
+    if !in_block {
+        start = statement_start.unwrap_or(start);
+    }
+    // Then look forwards for the corresponding end
+    let mut depth = 0;
+    let mut end = window_end;
+    for token in after.split_ascii_whitespace() {
+        match token {
+            "${" => depth += 1,
+            "$}" if depth > 0 => depth -= 1,
+            "$}" if in_block => {
+                end = cursor + offset(&after, token);
+                break;
+            }
+            "$." if !in_block && depth == 0 => {
+                end = cursor + offset(&after, token);
+                break;
+            }
+            _ => {}
+        }
+    }
+    start..end
+}
+
+/// Highlights the occurrences of a math token in the `${ $}` block enclosing the cursor,
+/// or in the statement enclosing it if it is not in a block.
+fn math_token_scope_highlights(text: &Rope, cursor: usize, token: &str) -> Vec<DocumentHighlight> {
+    let scope = enclosing_scope(text, cursor);
+    let source = text.cow_for_range(scope.clone());
+    let bytes = source.as_bytes();
+    memmem::find_iter(bytes, token)
+        .filter(|&start| {
+            let end = start + token.len();
+            (start == 0 || bytes[start - 1].is_ascii_whitespace())
+                && (end == bytes.len() || bytes[end].is_ascii_whitespace())
+        })
+        .take(MAX_HIGHLIGHTS)
+        .map(|start| DocumentHighlight {
+            range: Range::new(
+                text.byte_to_lsp_position(scope.start + start),
+                text.byte_to_lsp_position(scope.start + start + token.len()),
+            ),
+            kind: None,
+        })
+        .collect()
+}
+
+/// Highlights the step with the given name, and all hypothesis references to it
+fn step_name_highlights(worksheet: &ProofWorksheet, name: &str) -> Vec<DocumentHighlight> {
+    let mut highlights = vec![];
+    for (step_idx, step_info) in worksheet.steps.iter().enumerate() {
+        if worksheet.step_name(step_idx) == name {
+            highlights.push(DocumentHighlight {
+                range: worksheet.span_range(step_idx, step_info.step.name_span()),
+                kind: Some(DocumentHighlightKind::WRITE),
+            });
+        }
+        for hyp_span in step_info.step.hyps() {
+            if hyp_span.as_ref(&step_info.source) == name {
+                highlights.push(DocumentHighlight {
+                    range: worksheet.span_range(step_idx, hyp_span),
+                    kind: Some(DocumentHighlightKind::READ),
+                });
+            }
+        }
+    }
+    highlights
+}
+
+/// Highlights all the steps applying the given label
+fn step_label_highlights(worksheet: &ProofWorksheet, label: &str) -> Vec<DocumentHighlight> {
+    (0..worksheet.steps.len())
+        .filter(|&step_idx| worksheet.step_label(step_idx) == label.as_bytes())
+        .map(|step_idx| DocumentHighlight {
+            range: worksheet.span_range(step_idx, worksheet.steps[step_idx].step.label_span()),
+            kind: None,
+        })
+        .collect()
+}
+
+/// Highlights all occurrences of a math token in the formulas of a proof worksheet
+fn math_token_highlights(worksheet: &ProofWorksheet, token: &str) -> Vec<DocumentHighlight> {
+    let mut highlights = vec![];
+    for step_info in &worksheet.steps {
+        if let Some(formula_span) = step_info.step.formula_span() {
+            let formula = formula_span.as_ref(&step_info.source);
+            let offset = formula_span.as_range(step_info.byte_idx).start;
+            for formula_token in formula.split_ascii_whitespace() {
+                if formula_token == token {
+                    let start =
+                        offset + formula_token.as_ptr() as usize - formula.as_ptr() as usize;
+                    highlights.push(DocumentHighlight {
+                        range: Range::new(
+                            worksheet.byte_to_lsp_position(start),
+                            worksheet.byte_to_lsp_position(start + token.len()),
+                        ),
+                        kind: None,
+                    });
+                }
+            }
+        }
+    }
+    highlights
+}
+
+/// Highlights the occurrences of the step name, label or math token at the given position
+/// of a proof worksheet
+fn mmp_highlights(worksheet: &ProofWorksheet, pos: Position) -> Vec<DocumentHighlight> {
+    let (step_idx, offset) = match worksheet.step_at(pos) {
+        (Some(step_idx), offset) => (step_idx, offset),
+        (None, _) => return vec![],
+    };
+    let step_info = &worksheet.steps[step_idx];
+    let step = &step_info.step;
+    // The cursor may be just after the last character of a token
+    let contains = |range: std::ops::Range<usize>| range.start <= offset && offset <= range.end;
+    if contains(step.name_span().as_range(0)) {
+        return step_name_highlights(worksheet, worksheet.step_name(step_idx));
+    }
+    if let Some(hyp_span) = step.hyps().find(|hyp_span| contains(hyp_span.as_range(0))) {
+        let name = hyp_span.as_ref(&step_info.source);
+        return if name == "?" {
+            vec![]
+        } else {
+            step_name_highlights(worksheet, name)
+        };
+    }
+    if contains(step.label_span().as_range(0)) {
+        return step_label_highlights(worksheet, step.label(&step_info.source));
+    }
+    if let Some(formula_span) = step.formula_span() {
+        let formula = formula_span.as_ref(&step_info.source);
+        let formula_start = formula_span.as_range(0).start;
+        for token in formula.split_ascii_whitespace() {
+            let start = formula_start + token.as_ptr() as usize - formula.as_ptr() as usize;
+            if contains(start..start + token.len()) {
+                return math_token_highlights(worksheet, token);
+            }
+        }
+    }
+    vec![]
+}
+
+pub(crate) fn document_highlight(
+    path: FileRef,
+    pos: Position,
+    vfs: &Vfs,
+    db: Database,
+    cancel: &Cancellation,
+) -> Result<Option<Vec<DocumentHighlight>>, ServerError> {
+    let source = vfs.source(path.clone(), &db)?;
+    let highlights = match &source {
+        FileContents::MMFile(text) => {
+            let (word, _) = word_at(pos, source.clone());
+            if word.is_empty() {
+                return Ok(None);
+            }
+            match db.statement(word.as_bytes()) {
+                Some(stmt) => label_highlights(stmt, &path, text, &db, cancel)?,
+                None => math_token_scope_highlights(text, text.lsp_position_to_byte(pos), &word),
+            }
+        }
+        FileContents::MMPFile(worksheet) => mmp_highlights(worksheet, pos),
+    };
+    Ok(Some(highlights))
+}
@@ -0,0 +1,106 @@
+use crate::document_highlight::document_highlight;
+use crate::server::Cancellation;
+use crate::test_fixtures::{open_db, open_worksheet, TEST_DB};
+use crate::util::FileRef;
+use crate::vfs::Vfs;
+use lsp_types::*;
+use metamath_knife::Database;
+
+/// The ranges of the highlights at the given position
+fn highlight_ranges(path: FileRef, pos: Position, vfs: &Vfs, db: Database) -> Vec<Range> {
+    document_highlight(path, pos, vfs, db, &Cancellation::default())
+        .unwrap()
+        .unwrap()
+        .iter()
+        .map(|highlight| highlight.range)
+        .collect()
+}
+
+#[test]
+fn document_highlight_math_token() {
+    let (path, vfs, db) = open_db("highlight_math.mm", TEST_DB);
+    let range =
+        |line, start| Range::new(Position::new(line, start), Position::new(line, start + 2));
+    // The `ph` variable in the `maj` hypothesis: only the occurrences in the same block
+    let ranges = highlight_ranges(path.clone(), Position::new(7, 16), &vfs, db.clone());
+    assert_eq!(ranges, vec![range(6, 14), range(7, 16)]);
+    // The `ph` variable in `ax-1`, which is not in a block: only the occurrences in the statement
+    let ranges = highlight_ranges(path.clone(), Position::new(10, 13), &vfs, db.clone());
+    assert_eq!(ranges, vec![range(10, 13), range(10, 27)]);
+    // Tokens only match as a whole, `wph` is not highlighted
+    let ranges = highlight_ranges(path, Position::new(2, 12), &vfs, db);
+    assert_eq!(ranges, vec![range(2, 11)]);
+}
+
+#[test]
+fn document_highlight_label() {
+    let text = format!(
+        "{}$( See ~ ax-1 . $)\nth1 $p |- ( ph -> ( ph -> ph ) ) $= wph wph ax-1 $.\n",
+        TEST_DB
+    );
+    let (path, vfs, db) = open_db("highlight_label.mm", &text);
+    let mut highlights = document_highlight(
+        path,
+        Position::new(10, 1),
+        &vfs,
+        db,
+        &Cancellation::default(),
+    )
+    .unwrap()
+    .unwrap();
+    highlights.sort_by_key(|highlight| highlight.range.start);
+    let highlight = |line, start, end, kind| DocumentHighlight {
+        range: Range::new(Position::new(line, start), Position::new(line, end)),
+        kind: Some(kind),
+    };
+    assert_eq!(
+        highlights,
+        vec![
+            highlight(10, 0, 4, DocumentHighlightKind::WRITE),
+            highlight(15, 9, 13, DocumentHighlightKind::READ),
+            highlight(16, 44, 48, DocumentHighlightKind::READ),
+        ]
+    );
+}
+
+#[test]
+fn document_highlight_step_name() {
+    let (_, vfs, db) = open_db("highlight_step.mm", TEST_DB);
+    let path = open_worksheet(
+        "highlight_step.mmp",
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1      |- ph
+2::ax-1        |- ( ph -> ( ps -> ph ) )
+qed:1,2:ax-mp  |- ( ps -> ph )
+",
+        &vfs,
+        &db,
+    );
+    let highlights = document_highlight(
+        path,
+        Position::new(4, 4),
+        &vfs,
+        db,
+        &Cancellation::default(),
+    )
+    .unwrap()
+    .unwrap();
+    let highlights: Vec<_> = highlights
+        .into_iter()
+        .map(|highlight| (highlight.range, highlight.kind))
+        .collect();
+    assert_eq!(
+        highlights,
+        vec![
+            (
+                Range::new(Position::new(2, 1), Position::new(2, 2)),
+                Some(DocumentHighlightKind::WRITE)
+            ),
+            (
+                Range::new(Position::new(4, 4), Position::new(4, 5)),
+                Some(DocumentHighlightKind::READ)
+            ),
+        ]
+    );
+}
@@ -0,0 +1,140 @@
+//! Provides folding ranges
+//! In Metamath database files, outline sections, multi-line comments, blocks and proofs can be folded.
+//! In proof worksheets, multi-line steps and the proof can be folded.
+
+use crate::definition::span_range;
+use crate::outline::is_in_file;
+use crate::proof::ProofWorksheet;
+use crate::rope_ext::RopeExt;
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::outline::OutlineNodeRef;
+use metamath_knife::parser::HeadingLevel;
+use metamath_knife::Database;
+
+/// Builds a folding range between the given lines, if they differ
+fn folding_range(
+    start_line: u32,
+    end_line: u32,
+    kind: Option<FoldingRangeKind>,
+) -> Option<FoldingRange> {
+    (end_line > start_line).then(|| FoldingRange {
+        start_line,
+        start_character: None,
+        end_line,
+        end_character: None,
+        kind,
+    })
+}
+
+/// Collects the folding ranges of the outline sections of the given file
+fn section_ranges(
+    node: OutlineNodeRef,
+    path: &FileRef,
+    vfs: &Vfs,
+    db: &Database,
+    ranges: &mut Vec<FoldingRange>,
+) {
+    for child in node.children_iter() {
+        if child.get_level() == HeadingLevel::Statement {
+            continue;
+        }
+        if is_in_file(&child, path, db) {
+            if let Some(range) = span_range(child.get_statement(), child.get_span(), vfs, db) {
+                ranges.extend(folding_range(
+                    range.start.line,
+                    range.end.line,
+                    Some(FoldingRangeKind::Region),
+                ));
+            }
+        }
+        section_ranges(child, path, vfs, db, ranges);
+    }
+}
+
+/// Lexes a Metamath database file, and collects the folding ranges of
+/// multi-line comments, `${ ... $}` blocks and `$= ... $.` proofs.
+fn mm_ranges(text: &str, ranges: &mut Vec<FoldingRange>) {
+    let mut comment_start = None;
+    let mut proof_start = None;
+    let mut block_starts = vec![];
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_idx = line_idx as u32;
+        for token in line.split_ascii_whitespace() {
+            if let Some(start_line) = comment_start {
+                if token == "$)" {
+                    ranges.extend(folding_range(
+                        start_line,
+                        line_idx,
+                        Some(FoldingRangeKind::Comment),
+                    ));
+                    comment_start = None;
+                }
+                continue;
+            }
+            match token {
+                "$(" => comment_start = Some(line_idx),
+                "${" => block_starts.push(line_idx),
+                "$}" => {
+                    if let Some(start_line) = block_starts.pop() {
+                        ranges.extend(folding_range(start_line, line_idx, None));
+                    }
+                }
+                "$=" => proof_start = Some(line_idx),
+                "$." => {
+                    if let Some(start_line) = proof_start.take() {
+                        ranges.extend(folding_range(start_line, line_idx, None));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Collects the folding ranges of multi-line steps, and of the proof, in a proof worksheet
+fn mmp_ranges(worksheet: &ProofWorksheet, ranges: &mut Vec<FoldingRange>) {
+    let line_count = |s: &str| bytecount::count(s.as_bytes(), b'\n') as u32;
+    for step_info in &worksheet.steps {
+        let source = step_info.source.as_str();
+        let start_line = step_info.line_idx as u32;
+        // The proof, if any, follows the last step
+        let (step_text, proof_text) = match source.find("\n$=") {
+            Some(idx) => (&source[..idx], Some(&source[idx + 1..])),
+            None => (source, None),
+        };
+        ranges.extend(folding_range(
+            start_line,
+            start_line + line_count(step_text.trim_end()),
+            None,
+        ));
+        if let Some(proof_text) = proof_text {
+            let proof_start = start_line + line_count(step_text) + 1;
+            let proof_end = proof_text.find("$.").unwrap_or(proof_text.len());
+            ranges.extend(folding_range(
+                proof_start,
+                proof_start + line_count(&proof_text[..proof_end]),
+                None,
+            ));
+        }
+    }
+}
+
+pub(crate) fn folding_ranges(
+    path: FileRef,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Vec<FoldingRange>, ServerError> {
+    let mut ranges = vec![];
+    match vfs.source(path.clone(), &db)? {
+        FileContents::MMFile(text) => {
+            section_ranges(OutlineNodeRef::root_node(&db), &path, vfs, &db, &mut ranges);
+            mm_ranges(&text.cow_for_range(..), &mut ranges);
+        }
+        FileContents::MMPFile(worksheet) => mmp_ranges(&worksheet, &mut ranges),
+    }
+    Ok(ranges)
+}
@@ -0,0 +1,52 @@
+use crate::folding_range::folding_ranges;
+use crate::test_fixtures::{open_db, open_worksheet, TEST_DB};
+use lsp_types::*;
+
+/// The start and end lines, and the kind, of folding ranges
+fn folded_lines(ranges: Vec<FoldingRange>) -> Vec<(u32, u32, Option<FoldingRangeKind>)> {
+    ranges
+        .into_iter()
+        .map(|range| (range.start_line, range.end_line, range.kind))
+        .collect()
+}
+
+#[test]
+fn folding_ranges_in_database() {
+    let text = format!(
+        "$( A comment\n   over two lines $)\n{}th1 $p |- ( ph -> ( ph -> ph ) ) $=\n  wph wph\n  ax-1 $.\n",
+        TEST_DB
+    );
+    let (path, vfs, db) = open_db("folding_database.mm", &text);
+    let ranges = folded_lines(folding_ranges(path, &vfs, db).unwrap());
+    assert_eq!(
+        ranges,
+        vec![
+            (0, 1, Some(FoldingRangeKind::Comment)),
+            (7, 11, None),
+            (13, 16, None),
+            (17, 19, None),
+        ]
+    );
+}
+
+#[test]
+fn folding_ranges_in_worksheet() {
+    let (_, vfs, db) = open_db("folding_worksheet.mm", TEST_DB);
+    let path = open_worksheet(
+        "folding_worksheet.mmp",
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1      |- ph
+2::ax-1        |- ( ph
+                 -> ( ps -> ph ) )
+qed:1,2:ax-mp  |- ( ps -> ph )
+
+$=    wph wps wph wi a1i.1 wph wps ax-1
+    ax-mp $.
+",
+        &vfs,
+        &db,
+    );
+    let ranges = folded_lines(folding_ranges(path, &vfs, db).unwrap());
+    assert_eq!(ranges, vec![(3, 4, None), (7, 8, None)]);
+}
@@ -0,0 +1,30 @@
+//! Handles proof generation requests
+
+use crate::proof::ProofFormat;
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::Database;
+use std::collections::HashMap;
+
+pub(crate) fn generate_proof(
+    path: FileRef,
+    format: ProofFormat,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Option<WorkspaceEdit>, ServerError> {
+    let uri = path.url().clone();
+    if let FileContents::MMPFile(worksheet) = vfs.source(path, &db)? {
+        let edit = worksheet.proof_edit(format)?;
+        let mut changes = HashMap::new();
+        changes.insert(uri, vec![edit]);
+        Ok(Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..WorkspaceEdit::default()
+        }))
+    } else {
+        Ok(None)
+    }
+}
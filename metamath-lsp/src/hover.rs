@@ -1,24 +1,48 @@
 //! Provides hover information
 
+use crate::definition::find_definition;
 use crate::definition::find_statement;
+use crate::definition::syntax_axiom;
+use crate::proof::ProofWorksheet;
 use crate::server::word_at;
+use crate::typesetting::html_to_text;
+use crate::typesetting::Typesetting;
 use crate::util::FileRef;
+use crate::vfs::FileContents;
 use crate::vfs::Vfs;
 use crate::ServerError;
 use lsp_types::*;
 use metamath_knife::comment_parser::CommentItem;
+use metamath_knife::nameck::SymbolType;
 use metamath_knife::statement::as_str;
 use metamath_knife::statement::StatementRef;
 use metamath_knife::Database;
 use std::fmt::Write;
 
-fn comment_markup_format(
+/// Writes the math string of a statement, rendered as Unicode text if typesetting definitions are given
+fn write_math(
+    out: &mut String,
+    stmt: &StatementRef<'_>,
+    typesetting: Option<&Typesetting>,
+) -> Result<(), ServerError> {
+    for token in stmt.math_iter() {
+        match typesetting {
+            Some(typesetting) => write!(out, "{} ", typesetting.render_token(&token))?,
+            None => write!(out, "{} ", as_str(&token))?,
+        }
+    }
+    Ok(())
+}
+
+/// Formats the statement and its hypotheses as Markdown,
+/// rendered as Unicode text if typesetting definitions are given
+fn statement_markup_format(
     stmt: StatementRef<'_>,
     db: &Database,
-) -> Result<MarkedString, ServerError> {
-    let mut out = String::new();
+    typesetting: Option<&Typesetting>,
+    out: &mut String,
+) -> Result<(), ServerError> {
     writeln!(out, "## {}", as_str(stmt.label()))?;
-    let buf = &stmt.segment().segment.buffer;
     if stmt.statement_type().is_assertion() {
         writeln!(out, "```metamath")?;
         // Hypotheses
@@ -31,20 +55,28 @@ fn comment_markup_format(
         {
             let hyp_stmt = db.statement_by_label(label).unwrap();
             write!(out, "{} ", as_str(hyp_stmt.label()))?;
-            for token in hyp_stmt.math_iter() {
-                write!(out, "{} ", as_str(&token))?;
-            }
+            write_math(out, &hyp_stmt, typesetting)?;
             writeln!(out)?;
         }
 
         // Statement assertion
         write!(out, "{}   ", as_str(stmt.label()))?;
-        for token in stmt.math_iter() {
-            write!(out, "{} ", as_str(&token))?;
-        }
+        write_math(out, &stmt, typesetting)?;
         writeln!(out, "\n```")?;
     }
+    Ok(())
+}
 
+/// Formats the statement, its hypotheses and its associated comment as Markdown,
+/// with math rendered as Unicode text if typesetting definitions are given
+pub(crate) fn comment_markup_format(
+    stmt: StatementRef<'_>,
+    db: &Database,
+    typesetting: Option<&Typesetting>,
+) -> Result<String, ServerError> {
+    let mut out = String::new();
+    statement_markup_format(stmt, db, typesetting, &mut out)?;
+    let buf = &stmt.segment().segment.buffer;
     if let Some(comment_stmt) = stmt.associated_comment() {
         writeln!(out, "---")?;
         for item in comment_stmt.comment_parser() {
@@ -53,7 +85,12 @@ fn comment_markup_format(
                 CommentItem::LineBreak(_) => writeln!(out, "\n")?,
                 CommentItem::StartMathMode(_) => write!(out, "`")?,
                 CommentItem::EndMathMode(_) => write!(out, " `")?,
-                CommentItem::MathToken(span) => write!(out, " {}", as_str(span.as_ref(buf)))?,
+                CommentItem::MathToken(span) => match typesetting {
+                    Some(typesetting) => {
+                        write!(out, " {}", typesetting.render_token(span.as_ref(buf)))?
+                    }
+                    None => write!(out, " {}", as_str(span.as_ref(buf)))?,
+                },
                 CommentItem::Label(_, span) => write!(out, "`~ {}`", as_str(span.as_ref(buf)))?,
                 CommentItem::Url(_, span) => {
                     write!(out, "[{url}]({url})", url = as_str(span.as_ref(buf)))?
@@ -68,24 +105,195 @@ fn comment_markup_format(
             }
         }
     }
-    Ok(MarkedString::String(out))
+    Ok(out)
+}
+
+/// Formats the information about a math symbol as Markdown:
+/// for a variable, its typecode, and for a constant, its syntax axiom and its definition.
+/// The rendering given by the typesetting definitions is also shown, if available.
+/// Statements are rendered as Unicode text if `unicode` is set.
+/// Returns `None` if the token is not a math symbol.
+fn symbol_markup_format(
+    token: &[u8],
+    db: &Database,
+    typesetting: &Typesetting,
+    unicode: bool,
+) -> Result<Option<String>, ServerError> {
+    let nset = db.name_result();
+    let symbol = match nset.lookup_symbol(token) {
+        Some(symbol) => symbol,
+        None => return Ok(None),
+    };
+    let mut out = String::new();
+    writeln!(out, "## `{}`", as_str(token))?;
+    let syntax_label = syntax_axiom(symbol.atom, db);
+    let syntax_stmt = syntax_label.and_then(|label| db.statement(nset.atom_name(label)));
+    let description = match symbol.stype {
+        SymbolType::Variable => match nset.lookup_float(token) {
+            Some(float) => format!(
+                "Variable of typecode `{}`",
+                as_str(nset.atom_name(float.typecode_atom))
+            ),
+            None => "Variable".to_string(),
+        },
+        SymbolType::Constant if db.grammar_result().typecodes().contains(&symbol.atom) => {
+            "Typecode".to_string()
+        }
+        SymbolType::Constant => match syntax_stmt
+            .as_ref()
+            .and_then(|stmt| stmt.math_iter().next())
+        {
+            Some(typecode) => format!("Constant of typecode `{}`", as_str(typecode.slice)),
+            None => "Constant".to_string(),
+        },
+    };
+    write!(out, "{}", description)?;
+    if let Some(alt_html) = typesetting.alt_html(token) {
+        let unicode = html_to_text(alt_html);
+        if !unicode.is_empty() {
+            write!(out, ", rendered as `{}`", unicode)?;
+        }
+        write!(out, " (HTML `{}`)", alt_html)?;
+    }
+    writeln!(out)?;
+    if matches!(symbol.stype, SymbolType::Constant) {
+        if let Some(syntax_stmt) = syntax_stmt {
+            let typesetting = unicode.then(|| typesetting);
+            writeln!(out, "---")?;
+            statement_markup_format(syntax_stmt, db, typesetting, &mut out)?;
+            if let Some(definition) = syntax_label.and_then(|label| find_definition(label, db)) {
+                writeln!(out, "---")?;
+                out.push_str(&comment_markup_format(definition, db, typesetting)?);
+            }
+        }
+    }
+    Ok(Some(out))
 }
 
+/// Formats the assertion applied by a proof worksheet step as Markdown, together with
+/// the substitutions found by unification, and the hypotheses expected after substitution.
+/// Formulas are rendered as Unicode text if typesetting definitions are given.
+fn step_markup_format(
+    worksheet: &ProofWorksheet,
+    step_idx: usize,
+    stmt: StatementRef<'_>,
+    db: &Database,
+    typesetting: Option<&Typesetting>,
+) -> Result<String, ServerError> {
+    let mut out = String::new();
+    statement_markup_format(stmt, db, typesetting, &mut out)?;
+    let render = |formula: &str| match typesetting {
+        Some(typesetting) => {
+            typesetting.render(formula.split_ascii_whitespace().map(str::as_bytes))
+        }
+        None => formula.to_string(),
+    };
+    let unification = match worksheet.step_unification(step_idx) {
+        Some(unification) => unification,
+        None => return Ok(out),
+    };
+    if let Some((message, _)) = &unification.error {
+        writeln!(out, "**{}**\n", message)?;
+    }
+    if !unification.substitutions.is_empty() {
+        writeln!(out, "Substitutions:\n```metamath")?;
+        for (variable, expression) in &unification.substitutions {
+            writeln!(
+                out,
+                "{} = {}",
+                render(variable),
+                expression.as_deref().map_or("?".to_string(), render)
+            )?;
+        }
+        writeln!(out, "```")?;
+    }
+    if !unification.expected_hyps.is_empty() {
+        let step_info = &worksheet.steps[step_idx];
+        let failed_hyp = unification.error.and_then(|(_, hyp_idx)| hyp_idx);
+        writeln!(out, "Expected hypotheses:\n```metamath")?;
+        for (hyp_idx, formula) in unification.expected_hyps.iter().enumerate() {
+            let name = step_info
+                .step
+                .hyps()
+                .nth(hyp_idx)
+                .map_or("?", |span| span.as_ref(&step_info.source));
+            write!(out, "{}: {}", name, render(formula))?;
+            if failed_hyp == Some(hyp_idx) {
+                write!(out, "   $( does not match $)")?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out, "```")?;
+    }
+    Ok(out)
+}
+
+/// Provides the hover for the label of a proof worksheet step, if the position is on one
+fn step_hover(
+    worksheet: &ProofWorksheet,
+    pos: Position,
+    db: &Database,
+    typesetting: Option<&Typesetting>,
+) -> Result<Option<Hover>, ServerError> {
+    let (step_idx, offset) = match worksheet.step_at(pos) {
+        (Some(step_idx), offset) => (step_idx, offset),
+        (None, _) => return Ok(None),
+    };
+    let label_span = worksheet.steps[step_idx].step.label_span();
+    let label_range = label_span.as_range(0);
+    // The cursor may be just after the last character of the label
+    if offset < label_range.start || offset > label_range.end {
+        return Ok(None);
+    }
+    let stmt = match db.statement(worksheet.step_label(step_idx)) {
+        Some(stmt) => stmt,
+        None => return Ok(None),
+    };
+    Ok(Some(Hover {
+        range: Some(worksheet.span_range(step_idx, label_span)),
+        contents: HoverContents::Scalar(MarkedString::String(step_markup_format(
+            worksheet,
+            step_idx,
+            stmt,
+            db,
+            typesetting,
+        )?)),
+    }))
+}
+
+/// Provides the hover at the given position.
+/// The typesetting definitions are used to show the rendering of math symbols,
+/// and to render formulas as Unicode text if `unicode` is set.
 pub(crate) fn hover(
     path: FileRef,
     pos: Position,
     vfs: &Vfs,
     db: Database,
+    typesetting: &Typesetting,
+    unicode: bool,
 ) -> Result<Option<Hover>, ServerError> {
     let text = vfs.source(path, &db)?;
+    let unicode_typesetting = unicode.then(|| typesetting);
+    if let FileContents::MMPFile(worksheet) = &text {
+        if let Some(hover) = step_hover(worksheet, pos, &db, unicode_typesetting)? {
+            return Ok(Some(hover));
+        }
+    }
     let (word, range) = word_at(pos, text);
-    if let Some(stmt) = find_statement(word.as_bytes(), &db) {
+    if let Some(markup) = symbol_markup_format(word.as_bytes(), &db, typesetting, unicode)? {
+        Ok(Some(Hover {
+            range: Some(range),
+            contents: HoverContents::Scalar(MarkedString::String(markup)),
+        }))
+    } else if let Some(stmt) = find_statement(word.as_bytes(), &db) {
         Ok(Some(Hover {
             range: Some(range),
-            contents: HoverContents::Scalar(comment_markup_format(stmt, &db)?),
+            contents: HoverContents::Scalar(MarkedString::String(comment_markup_format(
+                stmt,
+                &db,
+                unicode_typesetting,
+            )?)),
         }))
-    //    } else if let Some(token) = db.name_pass().lookup_symbol() {
-    //
     } else {
         Ok(None)
     }
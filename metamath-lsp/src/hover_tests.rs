@@ -0,0 +1,55 @@
+use crate::hover::hover;
+use crate::test_fixtures::{open_db, TEST_DB};
+use crate::typesetting::Typesetting;
+use lsp_types::*;
+
+const TYPESETTING: &str = "$( $t althtmldef \"->\" as ' &rarr; '; $)\n";
+
+fn hover_text(hover: Option<Hover>) -> String {
+    match hover.unwrap().contents {
+        HoverContents::Scalar(MarkedString::String(text)) => text,
+        contents => panic!("Unexpected hover contents {:?}", contents),
+    }
+}
+
+#[test]
+fn hover_on_a_label() {
+    let (path, vfs, db) = open_db("hover_label.mm", TEST_DB);
+    let typesetting = Typesetting::default();
+    let result = hover(path, Position::new(10, 1), &vfs, db, &typesetting, false).unwrap();
+    let text = hover_text(result);
+    assert!(text.contains("ax-1"), "{}", text);
+    assert!(text.contains("|- ( ph -> ( ps -> ph ) )"), "{}", text);
+}
+
+#[test]
+fn hover_on_a_symbol() {
+    let text = format!("{}{}", TEST_DB, TYPESETTING);
+    let (path, vfs, db) = open_db("hover_symbol.mm", &text);
+    let typesetting = Typesetting::new(&db);
+    let result = hover(path, Position::new(0, 14), &vfs, db, &typesetting, false).unwrap();
+    let text = hover_text(result);
+    assert!(text.contains("rendered as `→`"), "{}", text);
+    assert!(text.contains("wff ( ph -> ps )"), "{}", text);
+}
+
+#[test]
+fn hover_rendered_as_unicode() {
+    let text = format!("{}{}", TEST_DB, TYPESETTING);
+    let (path, vfs, db) = open_db("hover_unicode.mm", &text);
+    let typesetting = Typesetting::new(&db);
+    let result = hover(
+        path.clone(),
+        Position::new(10, 1),
+        &vfs,
+        db.clone(),
+        &typesetting,
+        true,
+    );
+    let text = hover_text(result.unwrap());
+    assert!(text.contains("|- ( ph → ( ps → ph ) )"), "{}", text);
+    // Without the option, formulas stay in ASCII
+    let result = hover(path, Position::new(10, 1), &vfs, db, &typesetting, false);
+    let text = hover_text(result.unwrap());
+    assert!(text.contains("|- ( ph -> ( ps -> ph ) )"), "{}", text);
+}
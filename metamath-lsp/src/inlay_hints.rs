@@ -7,6 +7,7 @@ use std::sync::Arc;
 
 use crate::rope_ext::RopeExt;
 use crate::server::SERVER;
+use crate::typesetting::Typesetting;
 use crate::util::FileRef;
 use crate::vfs::FileContents;
 use crate::vfs::Vfs;
@@ -35,6 +36,8 @@ struct InlayHintContext<'a> {
     essentials: Vec<StatementAddress>,
     var2bit: std::collections::HashMap<Atom, usize>,
     setvars: Vec<usize>,
+    /// The typesetting definitions to render variables with, if Unicode rendering is enabled
+    typesetting: Option<Arc<Typesetting>>,
     reader: NameReader<'a>,
     source: Rope,
     hints: Vec<InlayHint>,
@@ -44,7 +47,11 @@ struct InlayHintContext<'a> {
 }
 
 impl<'a> InlayHintContext<'a> {
-    fn new(source: Rope, db: &'a Database) -> Result<Self, ServerError> {
+    fn new(
+        source: Rope,
+        typesetting: Option<Arc<Typesetting>>,
+        db: &'a Database,
+    ) -> Result<Self, ServerError> {
         Ok(Self {
             hints: vec![],
             wff: db
@@ -60,6 +67,7 @@ impl<'a> InlayHintContext<'a> {
             essentials: vec![],
             var2bit: std::collections::HashMap::new(),
             setvars: vec![],
+            typesetting,
             reader: NameReader::new(db.name_result()),
             nset: db.name_result(),
             scope: db.scope_result(),
@@ -92,8 +100,12 @@ impl<'a> InlayHintContext<'a> {
                                 ..Default::default()
                             });
                         }
+                        let name = self.nset.atom_name(frame.var_list[other]);
                         label_parts.push(InlayHintLabelPart {
-                            value: as_str(self.nset.atom_name(frame.var_list[other])).to_string(),
+                            value: match &self.typesetting {
+                                Some(typesetting) => typesetting.render_token(name),
+                                None => as_str(name).to_string(),
+                            },
                             ..Default::default()
                         });
                         first = false;
@@ -181,6 +193,7 @@ pub(crate) fn inlay_hints(
     range: Range,
     vfs: &Vfs,
     db: Database,
+    typesetting: Option<Arc<Typesetting>>,
 ) -> Result<Vec<InlayHint>, ServerError> {
     let guard = SERVER.workspace.lock().unwrap();
     if !guard.as_ref().unwrap().show_inlay_hints_dv {
@@ -201,7 +214,7 @@ pub(crate) fn inlay_hints(
         let last_statement = find_smallest_outline_containing(last_byte_idx as FilePos, root_node)
             .get_statement()
             .address();
-        let mut context = InlayHintContext::new(source, &db)?;
+        let mut context = InlayHintContext::new(source, typesetting, &db)?;
         if db.lt(&first_statement, &last_statement) {
             for statement in db
                 .statements_range_address(first_statement..=last_statement)
@@ -1,18 +1,63 @@
 #![allow(dead_code)]
 
+mod code_action;
+mod code_lens;
+#[cfg(test)]
+mod code_lens_tests;
+mod completion;
+#[cfg(test)]
+mod completion_tests;
 mod definition;
 mod diag;
+mod document_highlight;
+#[cfg(test)]
+mod document_highlight_tests;
+mod folding_range;
+#[cfg(test)]
+mod folding_range_tests;
+mod generate_proof;
 mod hover;
+#[cfg(test)]
+mod hover_tests;
 mod inlay_hints;
 mod outline;
 mod proof;
 mod references;
+#[cfg(test)]
+mod references_tests;
+mod rename;
+#[cfg(test)]
+mod rename_tests;
+mod renumber;
+#[cfg(test)]
+mod renumber_tests;
 mod rope_ext;
+mod semantic_tokens;
+#[cfg(test)]
+mod semantic_tokens_tests;
 mod server;
 mod show_proof;
+#[cfg(test)]
+mod show_proof_tests;
+mod store_proof;
+#[cfg(test)]
+mod store_proof_tests;
+#[cfg(test)]
+mod test_fixtures;
 mod types;
+mod typesetting;
+#[cfg(test)]
+mod typesetting_tests;
+mod unify;
 mod util;
+#[cfg(test)]
+mod util_tests;
 mod vfs;
+#[cfg(test)]
+mod vfs_tests;
+mod workspace_symbols;
+#[cfg(test)]
+mod workspace_symbols_tests;
 
 use crate::server::SERVER;
 use clap::{App, Arg};
@@ -1,7 +1,11 @@
-//! Provides the database outline : chapters and sections
+//! Provides the database outline : chapters and sections,
+//! and the outline of proof worksheets : their steps
 
 use crate::definition::span_range;
 use crate::definition::stmt_range;
+use crate::proof::ProofWorksheet;
+use crate::util::FileRef;
+use crate::vfs::FileContents;
 use crate::vfs::Vfs;
 use crate::ServerError;
 use lsp_types::*;
@@ -9,10 +13,16 @@ use metamath_knife::outline::OutlineNodeRef;
 use metamath_knife::parser::HeadingLevel;
 use metamath_knife::Database;
 
+/// Checks whether the given outline node is in the given file
+pub(crate) fn is_in_file(node: &OutlineNodeRef, path: &FileRef, db: &Database) -> bool {
+    path.is_named(db.statement_source_name(node.get_statement().address()))
+}
+
 #[allow(deprecated)] // workaround rust#60681 - ironically, the field "deprecated" is deprecated.
 /// Returns the LSP document symbol for a given outline
 fn document_symbol(
     node: OutlineNodeRef,
+    path: &FileRef,
     include_statements: bool,
     vfs: &Vfs,
     db: &Database,
@@ -35,13 +45,14 @@ fn document_symbol(
         deprecated: None,
         range,
         selection_range,
-        children: Some(children_symbols(node, include_statements, vfs, db)),
+        children: Some(children_symbols(node, path, include_statements, vfs, db)),
     })
 }
 
-/// Returns a list of all children nodes of `node`, as LSP `DocumentSymbol`
+/// Returns a list of all children nodes of `node` within the given file, as LSP `DocumentSymbol`
 fn children_symbols(
     node: OutlineNodeRef,
+    path: &FileRef,
     include_statements: bool,
     vfs: &Vfs,
     db: &Database,
@@ -49,16 +60,64 @@ fn children_symbols(
     let mut chapters = vec![];
     for chapter in node.children_iter() {
         if include_statements || chapter.get_level() != HeadingLevel::Statement {
-            if let Some(symbol) = document_symbol(chapter, include_statements, vfs, db) {
-                chapters.push(symbol);
+            if is_in_file(&chapter, path, db) {
+                chapters.extend(document_symbol(chapter, path, include_statements, vfs, db));
+            } else {
+                // A chapter from another file may contain sections from this file
+                chapters.extend(children_symbols(chapter, path, include_statements, vfs, db));
             }
         }
     }
     chapters
 }
 
-/// Builds the Outline
-pub(crate) fn outline(vfs: &Vfs, db: &Database) -> Result<Vec<DocumentSymbol>, ServerError> {
-    let root = OutlineNodeRef::root_node(db);
-    Ok(children_symbols(root, false, vfs, db))
+#[allow(deprecated)] // workaround rust#60681 - ironically, the field "deprecated" is deprecated.
+/// Returns the LSP document symbols for the steps of a proof worksheet
+fn worksheet_symbols(worksheet: &ProofWorksheet) -> Vec<DocumentSymbol> {
+    worksheet
+        .steps
+        .iter()
+        .enumerate()
+        .map(|(step_idx, step_info)| {
+            let step = &step_info.step;
+            let name = worksheet.step_name(step_idx);
+            let (name, kind) = if step.is_hyp() {
+                (format!("h{}", name), SymbolKind::FIELD)
+            } else if name == "qed" {
+                (name.to_string(), SymbolKind::FUNCTION)
+            } else {
+                (name.to_string(), SymbolKind::METHOD)
+            };
+            let end = step_info.byte_idx + step_info.source.trim_end().len();
+            DocumentSymbol {
+                name,
+                detail: Some(step.label(&step_info.source).to_string()),
+                kind,
+                tags: None,
+                deprecated: None,
+                range: Range::new(
+                    worksheet.byte_to_lsp_position(step_info.byte_idx),
+                    worksheet.byte_to_lsp_position(end),
+                ),
+                selection_range: worksheet.span_range(step_idx, step.name_span()),
+                children: None,
+            }
+        })
+        .collect()
+}
+
+/// Builds the Outline of the given file
+pub(crate) fn outline(
+    path: FileRef,
+    include_statements: bool,
+    vfs: &Vfs,
+    db: &Database,
+) -> Result<Vec<DocumentSymbol>, ServerError> {
+    match vfs.source(path.clone(), db)? {
+        FileContents::MMFile(_) => {
+            let root = OutlineNodeRef::root_node(db);
+            Ok(children_symbols(root, &path, include_statements, vfs, db))
+        }
+        FileContents::MMPFile(worksheet) => Ok(worksheet_symbols(&worksheet)),
+    }
 }
@@ -0,0 +1,513 @@
+//! Generation of the Metamath proof of a proof worksheet
+//! Builds the RPN proof from the steps, including the syntax proofs for the floating hypotheses,
+//! and writes it as a `$= ... $.` block.
+
+use super::step::Step;
+use super::worksheet::{Diag, StepIdx};
+use super::ProofWorksheet;
+use lsp_types::{Range as LspRange, TextEdit};
+use metamath_knife::formula::Substitutions;
+use metamath_knife::scopeck::Hyp;
+use metamath_knife::statement::{as_str, TokenPtr};
+use metamath_knife::{Formula, StatementType};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Maximum length of the lines of the generated proof
+const LINE_WIDTH: usize = 79;
+
+/// Indentation of the continuation lines of a proof in a proof worksheet
+const MMP_INDENT: usize = 6;
+
+/// The format in which a proof is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProofFormat {
+    /// Labels of the proof, in reverse polish notation
+    Normal,
+    /// Compressed format, where labels are referenced by letters, and sub-proofs are reused
+    Compressed,
+    /// Like the normal format, with each label prefixed by the hypothesis it fulfills
+    Explicit,
+}
+
+impl Default for ProofFormat {
+    fn default() -> Self {
+        Self::Compressed
+    }
+}
+
+/// A node of the proof tree: an assertion applied, with the nodes proving each of its hypotheses.
+/// Labels are kept as names, since the hypotheses of a new theorem are not in the database yet.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ProofNode<'a> {
+    label: TokenPtr<'a>,
+    hyps: Vec<(TokenPtr<'a>, usize)>,
+}
+
+/// Builds the proof tree of a worksheet.
+/// Identical sub-proofs are represented by the same node.
+struct ProofBuilder<'a> {
+    worksheet: &'a ProofWorksheet,
+    nodes: Vec<ProofNode<'a>>,
+    indices: HashMap<ProofNode<'a>, usize>,
+    step_nodes: HashMap<StepIdx, usize>,
+}
+
+impl<'a> ProofBuilder<'a> {
+    fn new(worksheet: &'a ProofWorksheet) -> Self {
+        Self {
+            worksheet,
+            nodes: vec![],
+            indices: HashMap::new(),
+            step_nodes: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, node: ProofNode<'a>) -> usize {
+        if let Some(&index) = self.indices.get(&node) {
+            return index;
+        }
+        let index = self.nodes.len();
+        self.indices.insert(node.clone(), index);
+        self.nodes.push(node);
+        index
+    }
+
+    /// Builds the syntax proof of the sub-formula at the given path.
+    /// The children of a syntax axiom node are in the order of the variables in the syntax axiom,
+    /// and are re-ordered according to its floating hypotheses.
+    fn syntax_proof(&mut self, formula: &Formula, path: &mut Vec<usize>) -> Result<usize, String> {
+        let worksheet = self.worksheet;
+        let db = &worksheet.db;
+        let label = formula.get_by_path(path).ok_or("Incomplete syntax tree")?;
+        if worksheet.work_vars.is_work_variable(label) {
+            return Err("The proof still contains work variables".to_string());
+        }
+        let stmt = db.statement_by_label(label).ok_or("Unknown syntax axiom")?;
+        let mut hyps = vec![];
+        if stmt.statement_type() != StatementType::Floating {
+            let frame = db
+                .scope_result()
+                .get(stmt.label())
+                .ok_or("Unknown syntax axiom")?;
+            let floats: Vec<_> = frame
+                .hypotheses
+                .iter()
+                .filter_map(|hyp| match hyp {
+                    Hyp::Floating(sa, ..) => Some(db.statement_by_address(*sa)),
+                    Hyp::Essential(..) => None,
+                })
+                .collect();
+            let variables: Vec<_> = stmt
+                .math_iter()
+                .skip(1)
+                .filter(|token| {
+                    floats
+                        .iter()
+                        .any(|float| float.math_iter().nth(1).map(|t| t.slice) == Some(token.slice))
+                })
+                .map(|token| token.slice)
+                .collect();
+            for float in floats {
+                let variable = float
+                    .math_iter()
+                    .nth(1)
+                    .ok_or("Malformed floating hypothesis")?;
+                let child_idx = variables
+                    .iter()
+                    .position(|&v| v == variable.slice)
+                    .ok_or("Variable not used in syntax axiom")?;
+                path.push(child_idx);
+                let child = self.syntax_proof(formula, path)?;
+                path.pop();
+                hyps.push((float.label(), child));
+            }
+        }
+        Ok(self.add(ProofNode {
+            label: stmt.label(),
+            hyps,
+        }))
+    }
+
+    /// Builds the proof of the given step
+    fn step_proof(&mut self, step_idx: StepIdx) -> Result<usize, String> {
+        if let Some(&node) = self.step_nodes.get(&step_idx) {
+            return Ok(node);
+        }
+        let worksheet = self.worksheet;
+        let db = &worksheet.db;
+        let step = &worksheet.steps[step_idx].step;
+        let step_name = worksheet.step_name(step_idx);
+        let label = worksheet.step_label(step_idx);
+        let formula = step
+            .formula()
+            .ok_or_else(|| format!("Step {step_name} has no formula"))?;
+        if step.is_hyp() {
+            let node = self.add(ProofNode {
+                label,
+                hyps: vec![],
+            });
+            self.step_nodes.insert(step_idx, node);
+            return Ok(node);
+        }
+        let stmt_formula = worksheet
+            .step_stmt_formula(step_idx)
+            .map_err(|_| format!("Step {step_name} has an unknown label"))?;
+        let mut substitutions = Substitutions::new();
+        formula
+            .unify(stmt_formula, &mut substitutions)
+            .map_err(|_| format!("Step {step_name} does not match its label"))?;
+        let frame = db
+            .scope_result()
+            .get(label)
+            .ok_or_else(|| format!("Step {step_name} has an unknown label"))?;
+        let essentials: Vec<_> = frame.as_ref(db).essentials().collect();
+        if essentials.len() != step.hyps().len() {
+            return Err(format!("Step {step_name} has a wrong hypothesis count"));
+        }
+        let mut essential_nodes = vec![];
+        for (hyp_idx, (_, hyp_formula)) in essentials.iter().enumerate() {
+            let hyp_name = worksheet.hyp_name(step_idx, hyp_idx);
+            let hyp_step_idx = worksheet
+                .steps_by_name
+                .get(hyp_name)
+                .copied()
+                .filter(|&hyp_step_idx| hyp_step_idx < step_idx)
+                .ok_or_else(|| {
+                    format!("Hypothesis {hyp_name} of step {step_name} is not a previous step")
+                })?;
+            worksheet
+                .step_formula(hyp_step_idx)
+                .ok_or_else(|| format!("Step {hyp_name} has no formula"))?
+                .unify(hyp_formula, &mut substitutions)
+                .map_err(|_| format!("Hypothesis {hyp_name} of step {step_name} does not match"))?;
+            essential_nodes.push(self.step_proof(hyp_step_idx)?);
+        }
+        let mut essential_nodes = essential_nodes.into_iter();
+        let mut hyps = vec![];
+        for hyp in &frame.hypotheses {
+            match hyp {
+                Hyp::Floating(sa, ..) => {
+                    let float = db.statement_by_address(*sa);
+                    let float_label = db
+                        .name_result()
+                        .lookup_label(float.label())
+                        .ok_or("Unknown floating hypothesis")?
+                        .atom;
+                    let sub_formula = substitutions.get(float_label).ok_or_else(|| {
+                        format!("Step {step_name} has a variable which is not determined")
+                    })?;
+                    hyps.push((float.label(), self.syntax_proof(sub_formula, &mut vec![])?));
+                }
+                Hyp::Essential(sa, _) => {
+                    let hyp_label = db.statement_by_address(*sa).label();
+                    hyps.push((hyp_label, essential_nodes.next().unwrap()));
+                }
+            }
+        }
+        let node = self.add(ProofNode { label, hyps });
+        self.step_nodes.insert(step_idx, node);
+        Ok(node)
+    }
+
+    /// Lists the floating hypotheses used in the given proof
+    fn collect_floats(&self, node: usize, floats: &mut HashSet<TokenPtr<'a>>) {
+        let node_ref = &self.nodes[node];
+        if node_ref.hyps.is_empty() {
+            let is_float = self
+                .worksheet
+                .db
+                .statement(node_ref.label)
+                .map_or(false, |stmt| {
+                    stmt.statement_type() == StatementType::Floating
+                });
+            if is_float {
+                floats.insert(node_ref.label);
+            }
+        }
+        for &(_, child) in &node_ref.hyps {
+            self.collect_floats(child, floats);
+        }
+    }
+
+    /// The mandatory hypotheses of the theorem being proven, in order.
+    ///
+    /// For a new theorem, these are the floating hypotheses of the variables used
+    /// in the hypothesis steps and the final step, in database order,
+    /// followed by the hypothesis steps, which become its essential hypotheses.
+    fn mandatory_hyps(&mut self, qed: StepIdx) -> Result<Vec<TokenPtr<'a>>, String> {
+        let worksheet = self.worksheet;
+        let db = &worksheet.db;
+        if let Some(sadd) = worksheet.sadd {
+            let frame = db
+                .scope_result()
+                .get(db.statement_by_address(sadd).label())
+                .ok_or("Unknown theorem")?;
+            return Ok(frame
+                .hypotheses
+                .iter()
+                .map(|hyp| {
+                    let (Hyp::Floating(sa, ..) | Hyp::Essential(sa, _)) = hyp;
+                    db.statement_by_address(*sa).label()
+                })
+                .collect());
+        }
+        let mut floats = HashSet::new();
+        let mut essentials = vec![];
+        for step_idx in 0..worksheet.steps.len() {
+            let step = &worksheet.steps[step_idx].step;
+            if step.is_hyp() || step_idx == qed {
+                let formula = step.formula().ok_or("Missing formula")?;
+                let root = self.syntax_proof(formula, &mut vec![])?;
+                self.collect_floats(root, &mut floats);
+                if step.is_hyp() {
+                    essentials.push(worksheet.step_label(step_idx));
+                }
+            }
+        }
+        Ok(db
+            .statements()
+            .filter(|stmt| {
+                stmt.statement_type() == StatementType::Floating && floats.contains(stmt.label())
+            })
+            .map(|stmt| stmt.label())
+            .chain(essentials)
+            .collect())
+    }
+
+    /// Writes the labels of the given proof in reverse polish notation,
+    /// prefixed by the hypothesis they fulfill in the explicit format.
+    fn write_rpn(
+        &self,
+        node: usize,
+        hyp: Option<TokenPtr<'a>>,
+        explicit: bool,
+        out: &mut ProofWriter,
+    ) {
+        let node_ref = &self.nodes[node];
+        for &(hyp_label, child) in &node_ref.hyps {
+            self.write_rpn(child, Some(hyp_label), explicit, out);
+        }
+        match hyp {
+            Some(hyp) if explicit => {
+                out.push_token(&format!("{}={}", as_str(hyp), as_str(node_ref.label)))
+            }
+            _ => out.push_token(as_str(node_ref.label)),
+        }
+    }
+
+    /// Counts the uses of each node, and lists the labels used in the order they first appear.
+    /// The sub-proofs of a node already counted are not visited again,
+    /// since they will be referenced as a whole in the compressed proof.
+    fn count_uses(&self, node: usize, uses: &mut [usize], labels: &mut Vec<TokenPtr<'a>>) {
+        uses[node] += 1;
+        if uses[node] > 1 {
+            return;
+        }
+        for &(_, child) in &self.nodes[node].hyps {
+            self.count_uses(child, uses, labels);
+        }
+        if !labels.contains(&self.nodes[node].label) {
+            labels.push(self.nodes[node].label);
+        }
+    }
+
+    /// Writes the letters of the compressed proof for the given node.
+    /// Sub-proofs used several times are tagged with `Z` the first time, and referenced afterwards.
+    fn write_compressed(
+        &self,
+        node: usize,
+        numbers: &HashMap<TokenPtr<'a>, usize>,
+        uses: &[usize],
+        saved: &mut HashMap<usize, usize>,
+        letters: &mut String,
+    ) {
+        if let Some(&number) = saved.get(&node) {
+            letters.push_str(&compressed_number(number));
+            return;
+        }
+        let node_ref = &self.nodes[node];
+        for &(_, child) in &node_ref.hyps {
+            self.write_compressed(child, numbers, uses, saved, letters);
+        }
+        letters.push_str(&compressed_number(numbers[&node_ref.label]));
+        if uses[node] > 1 && !node_ref.hyps.is_empty() {
+            saved.insert(node, numbers.len() + saved.len() + 1);
+            letters.push('Z');
+        }
+    }
+}
+
+/// Encodes a number in the compressed proof format:
+/// the last letter is between `A` and `T`, the preceding ones between `U` and `Y`.
+fn compressed_number(number: usize) -> String {
+    let mut n = number - 1;
+    let mut letters = vec![b'A' + (n % 20) as u8];
+    n /= 20;
+    while n > 0 {
+        n -= 1;
+        letters.push(b'U' + (n % 5) as u8);
+        n /= 5;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap()
+}
+
+/// Writes the tokens of a proof, breaking lines at the maximum width
+struct ProofWriter {
+    out: String,
+    line_len: usize,
+    indent: String,
+}
+
+impl ProofWriter {
+    /// A writer starting at the given column, and indenting continuation lines by `indent` spaces
+    fn new(column: usize, indent: usize) -> Self {
+        Self {
+            out: String::new(),
+            line_len: column,
+            indent: " ".repeat(indent),
+        }
+    }
+
+    fn push_token(&mut self, token: &str) {
+        if !self.out.is_empty() {
+            if self.line_len + 1 + token.len() > LINE_WIDTH {
+                self.out.push('\n');
+                self.out.push_str(&self.indent);
+                self.line_len = self.indent.len();
+            } else {
+                self.out.push(' ');
+                self.line_len += 1;
+            }
+        }
+        self.out.push_str(token);
+        self.line_len += token.len();
+    }
+
+    /// Pushes the letters of a compressed proof, which may be broken anywhere
+    fn push_letters(&mut self, mut letters: &str) {
+        while !letters.is_empty() {
+            let room = match LINE_WIDTH.saturating_sub(self.line_len + 1) {
+                0 => LINE_WIDTH - self.indent.len(),
+                room => room,
+            };
+            let len = room.min(letters.len());
+            self.push_token(&letters[..len]);
+            letters = &letters[len..];
+        }
+    }
+}
+
+impl ProofWorksheet {
+    /// The final step of the proof: the one named `qed`, or the last one
+    pub(crate) fn qed_step(&self) -> Option<StepIdx> {
+        self.steps_by_name
+            .get("qed")
+            .copied()
+            .or_else(|| self.steps.len().checked_sub(1))
+    }
+
+    /// Builds the proof of this worksheet, and writes its tokens.
+    /// This fails if any step is not valid or not complete.
+    fn write_proof(&self, format: ProofFormat, out: &mut ProofWriter) -> Result<(), String> {
+        // The hypotheses of a new theorem are not in the database yet, so their labels are unknown
+        let is_new_hyp = |step: &Step, diag: &Diag| {
+            self.sadd.is_none() && step.is_hyp() && matches!(diag, Diag::UnknownTheoremLabel(_))
+        };
+        if self.steps.iter().any(|step_info| {
+            let step = &step_info.step;
+            step.diags().any(|diag| !is_new_hyp(step, diag))
+        }) {
+            return Err("The proof worksheet still contains errors".to_string());
+        }
+        let qed = self.qed_step().ok_or("The proof worksheet has no steps")?;
+        if let Some(sadd) = self.sadd {
+            let theorem = self.db.statement_by_address(sadd);
+            if self.step_formula(qed) != self.db.stmt_parse_result().get_formula(&theorem) {
+                return Err("The last step does not match the theorem".to_string());
+            }
+        }
+        let mut builder = ProofBuilder::new(self);
+        let root = builder.step_proof(qed)?;
+        match format {
+            ProofFormat::Normal => builder.write_rpn(root, None, false, out),
+            ProofFormat::Explicit => builder.write_rpn(root, None, true, out),
+            ProofFormat::Compressed => {
+                // Mandatory hypotheses are numbered first, then the labels listed in parentheses
+                let mut numbers = HashMap::new();
+                for label in builder.mandatory_hyps(qed)? {
+                    numbers.insert(label, numbers.len() + 1);
+                }
+                let mut uses = vec![0; builder.nodes.len()];
+                let mut labels = vec![];
+                builder.count_uses(root, &mut uses, &mut labels);
+                out.push_token("(");
+                for label in labels {
+                    if !numbers.contains_key(label) {
+                        out.push_token(as_str(label));
+                        numbers.insert(label, numbers.len() + 1);
+                    }
+                }
+                out.push_token(")");
+                let mut letters = String::new();
+                builder.write_compressed(root, &numbers, &uses, &mut HashMap::new(), &mut letters);
+                out.push_letters(&letters);
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the proof of this worksheet, and writes it as a `$= ... $.` block.
+    pub fn generate_proof(&self, format: ProofFormat) -> Result<String, String> {
+        let mut out = ProofWriter::new(0, MMP_INDENT);
+        out.push_token("$=   ");
+        self.write_proof(format, &mut out)?;
+        out.push_token("$.");
+        Ok(out.out)
+    }
+
+    /// Builds the proof of this worksheet, and writes its tokens followed by the final `$.`,
+    /// as they appear in a database after the `$=` keyword.
+    /// The proof starts at the given column, and continuation lines are indented by `indent` spaces.
+    pub(crate) fn generate_proof_tokens(
+        &self,
+        format: ProofFormat,
+        column: usize,
+        indent: usize,
+    ) -> Result<String, String> {
+        let mut out = ProofWriter::new(column, indent);
+        self.write_proof(format, &mut out)?;
+        out.push_token("$.");
+        Ok(out.out)
+    }
+
+    /// Builds an edit writing the proof of this worksheet at its end:
+    /// replacing any existing `$=` block, or else before the closing `$)`.
+    pub fn proof_edit(&self, format: ProofFormat) -> Result<TextEdit, String> {
+        let proof = self.generate_proof(format)?;
+        let step_info = self
+            .steps
+            .last()
+            .ok_or("The proof worksheet has no steps")?;
+        let source = &step_info.source;
+        let (range, new_text) = if let Some(start) = source.find("\n$=").map(|idx| idx + 1) {
+            let end = source[start..]
+                .find("$.")
+                .map_or(source.len(), |idx| start + idx + 2);
+            (start..end, proof)
+        } else if let Some(start) = source.rfind("\n$)").map(|idx| idx + 1) {
+            (start..start, format!("{proof}\n\n"))
+        } else {
+            (source.len()..source.len(), format!("\n{proof}\n"))
+        };
+        Ok(TextEdit {
+            range: LspRange {
+                start: self.byte_to_lsp_position(step_info.byte_idx + range.start),
+                end: self.byte_to_lsp_position(step_info.byte_idx + range.end),
+            },
+            new_text,
+        })
+    }
+}
@@ -1,7 +1,14 @@
+mod generate;
+mod quick_fix;
+mod rename;
 mod step;
+mod unify;
+mod work_variables;
 mod worksheet;
 
 #[cfg(test)]
 mod worksheet_tests;
 
+pub use generate::ProofFormat;
+pub(crate) use work_variables::work_variable_typecode;
 pub use worksheet::ProofWorksheet;
@@ -0,0 +1,217 @@
+//! Quick fixes for the diagnostics of a proof worksheet
+
+use super::unify::compatible;
+use super::worksheet::{Diag, StepIdx};
+use super::ProofWorksheet;
+use lsp_types::{Diagnostic as LspDiagnostic, Range as LspRange, TextEdit};
+use metamath_knife::formula::Substitutions;
+use metamath_knife::statement::as_str;
+use metamath_knife::Formula;
+use std::ops::Bound;
+
+/// Maximum number of labels suggested to replace an unknown label
+const MAX_LABEL_SUGGESTIONS: usize = 5;
+
+/// Maximum edit distance between an unknown label and the labels suggested to replace it
+const MAX_LABEL_DISTANCE: usize = 2;
+
+/// A fix for a diagnostic of a step
+pub(crate) struct QuickFix {
+    /// The description of the fix, shown to the user
+    pub(crate) title: String,
+    /// The diagnostic fixed
+    pub(crate) diagnostic: LspDiagnostic,
+    /// The edit to apply to the worksheet
+    pub(crate) edit: TextEdit,
+}
+
+/// The Levenshtein distance between two labels, i.e. the number of characters
+/// to insert, delete or substitute to get from one to the other,
+/// or `None` if it is more than the given maximum.
+fn edit_distance(a: &[u8], b: &[u8], max: usize) -> Option<usize> {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+        // The distances in the next rows are at least the minimum of this row
+        if row.iter().min().map_or(false, |&min| min > max) {
+            return None;
+        }
+    }
+    (row[b.len()] <= max).then(|| row[b.len()])
+}
+
+impl ProofWorksheet {
+    /// The labels of the assertions in scope which are close to the given unknown label,
+    /// the closest first.
+    /// The search stops once enough labels at the smallest possible distance are found.
+    fn close_labels(&self, label: &[u8]) -> Vec<String> {
+        let mut matches = vec![];
+        // The number of labels found at distance 1, since the unknown label is at least that far
+        let mut closest_count = 0;
+        for stmt in self
+            .db
+            .statements_range_address((Bound::Unbounded, self.scope_end()))
+        {
+            if !stmt.statement_type().is_assertion() {
+                continue;
+            }
+            // The distance is at least the difference of lengths
+            if stmt.label().len() > label.len() + MAX_LABEL_DISTANCE
+                || label.len() > stmt.label().len() + MAX_LABEL_DISTANCE
+            {
+                continue;
+            }
+            if let Some(distance) = edit_distance(stmt.label(), label, MAX_LABEL_DISTANCE) {
+                matches.push((distance, as_str(stmt.label())));
+                if distance <= 1 {
+                    closest_count += 1;
+                    if closest_count == MAX_LABEL_SUGGESTIONS {
+                        break;
+                    }
+                }
+            }
+        }
+        matches.sort();
+        matches
+            .into_iter()
+            .take(MAX_LABEL_SUGGESTIONS)
+            .map(|(_, label)| label.to_string())
+            .collect()
+    }
+
+    /// Derives the formula of a step from the formulas of its hypotheses,
+    /// if they determine all the variables of the assertion applied.
+    fn derived_formula(&self, step_idx: StepIdx) -> Option<Formula> {
+        let sref = self.db.statement(self.step_label(step_idx))?;
+        let stmt_formula = self.db.stmt_parse_result().get_formula(&sref)?;
+        let frame = self.db.scope_result().get(sref.label())?;
+        let step = &self.steps[step_idx].step;
+        let mut substitutions = Substitutions::new();
+        for (hyp_idx, (_, hyp_formula)) in frame.as_ref(&self.db).essentials().enumerate() {
+            let formula = step
+                .hyps()
+                .nth(hyp_idx)
+                .and_then(|_| self.steps_by_name.get(self.hyp_name(step_idx, hyp_idx)))
+                .and_then(|&hyp_step_idx| self.step_formula(hyp_step_idx));
+            if let Some(formula) = formula {
+                let mut hyp_subst = Substitutions::new();
+                if formula.unify(hyp_formula, &mut hyp_subst).is_ok()
+                    && compatible(&substitutions, &hyp_subst)
+                {
+                    substitutions.extend(&hyp_subst);
+                }
+            }
+        }
+        self.is_determined(sref, &substitutions)
+            .then(|| stmt_formula.substitute(&substitutions))
+    }
+
+    /// The formula a step shall have, when its formula does not match:
+    /// the database hypothesis for hypothesis steps, the theorem's assertion for the `qed` step,
+    /// or the formula derived from the hypotheses for the other steps.
+    fn expected_formula(&self, step_idx: StepIdx, diag: &Diag) -> Option<(&'static str, Formula)> {
+        let provider = self.db.stmt_parse_result();
+        match diag {
+            Diag::HypothesisDoesNotMatch if self.steps[step_idx].step.is_hyp() => {
+                let sref = self.db.statement(self.step_label(step_idx))?;
+                Some(("database hypothesis", provider.get_formula(&sref)?.clone()))
+            }
+            Diag::HypothesisDoesNotMatch => {
+                let sref = self.db.statement_by_address(self.sadd?);
+                Some(("theorem's assertion", provider.get_formula(&sref)?.clone()))
+            }
+            Diag::UnificationFailed => Some((
+                "formula derived from the hypotheses",
+                self.derived_formula(step_idx)?,
+            )),
+            _ => None,
+        }
+    }
+
+    /// The fixes for the given diagnostic of a step
+    fn diag_fixes(&self, step_idx: StepIdx, diag: &Diag) -> Vec<(String, TextEdit)> {
+        let step_info = &self.steps[step_idx];
+        let step = &step_info.step;
+        match diag {
+            Diag::WrongHypCount { expected, actual } if actual < expected => {
+                let hyp_names: Vec<_> = step
+                    .hyps()
+                    .map(|span| span.as_ref(&step_info.source))
+                    .chain(std::iter::repeat("?").take(expected - actual))
+                    .collect();
+                vec![(
+                    "Add placeholders for the missing hypotheses".to_string(),
+                    self.replace_edit(step_idx, step.hyps_span(), hyp_names.join(",")),
+                )]
+            }
+            // This diagnostic is also reported on the step name when unifying, only fix it once
+            Diag::UnknownTheoremLabel(range)
+                if !step.is_hyp() && *range == step.label_span().as_range(0) =>
+            {
+                let label = step.label(&step_info.source);
+                if label == "?" {
+                    return vec![];
+                }
+                self.close_labels(label.as_bytes())
+                    .into_iter()
+                    .map(|new_label| {
+                        (
+                            format!("Replace with {}", new_label),
+                            self.replace_edit(step_idx, step.label_span(), new_label),
+                        )
+                    })
+                    .collect()
+            }
+            Diag::UnknownStepName(range) => {
+                // Insert the missing step just before the step referring to it
+                let name = &step_info.source[range.clone()];
+                let position = self.byte_to_lsp_position(step_info.byte_idx);
+                vec![(
+                    format!("Create step {}", name),
+                    TextEdit {
+                        range: LspRange::new(position, position),
+                        new_text: format!("{}::?\n", name),
+                    },
+                )]
+            }
+            _ => match self.expected_formula(step_idx, diag) {
+                Some((description, formula)) => vec![(
+                    format!("Replace with the {}", description),
+                    self.replace_formula_edit(step_idx, &formula),
+                )],
+                None => vec![],
+            },
+        }
+    }
+
+    /// The quick fixes for the diagnostics of the steps within the given range
+    pub(crate) fn quick_fixes(&self, range: LspRange) -> Vec<QuickFix> {
+        let first_step_idx = self.step_at(range.start).0.unwrap_or(0);
+        // A range ending before the first step only covers the header,
+        // one ending after the last step covers up to the last step
+        let last_step_idx = match self.step_at(range.end).0 {
+            Some(step_idx) => step_idx.min(self.steps.len() - 1),
+            None => return vec![],
+        };
+        let mut fixes = vec![];
+        for step_idx in first_step_idx..=last_step_idx {
+            let step_info = &self.steps[step_idx];
+            for diag in step_info.step.diags() {
+                for (title, edit) in self.diag_fixes(step_idx, diag) {
+                    fixes.push(QuickFix {
+                        title,
+                        diagnostic: self.lsp_diagnostic(step_info, diag),
+                        edit,
+                    });
+                }
+            }
+        }
+        fixes
+    }
+}
@@ -0,0 +1,83 @@
+//! Renaming and renumbering of the steps of a proof worksheet
+
+use super::ProofWorksheet;
+use lsp_types::{Position, Range as LspRange, TextEdit};
+use std::collections::HashMap;
+
+/// Checks whether the given string is a valid step name
+fn is_valid_step_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase())
+}
+
+impl ProofWorksheet {
+    /// Returns the name of the step at the given position, and its range,
+    /// if the position is on a step name or on a hypothesis reference.
+    pub(crate) fn step_name_at(&self, position: Position) -> Option<(&str, LspRange)> {
+        let (step_idx, offset) = self.step_at(position);
+        let step_idx = step_idx?;
+        let step_info = &self.steps[step_idx];
+        // The cursor may be just after the last character of the name
+        let contains = |range: std::ops::Range<usize>| range.start <= offset && offset <= range.end;
+        let name_span = std::iter::once(step_info.step.name_span())
+            .chain(step_info.step.hyps())
+            .find(|span| contains(span.as_range(0)))?;
+        let name = name_span.as_ref(&step_info.source);
+        (name != "?").then(|| (name, self.span_range(step_idx, name_span)))
+    }
+
+    /// Builds the edits renaming steps, replacing both their names
+    /// and the hypothesis references to them.
+    fn rename_steps_edits(&self, new_names: &HashMap<&str, String>) -> Vec<TextEdit> {
+        let mut edits = vec![];
+        for (step_idx, step_info) in self.steps.iter().enumerate() {
+            let step = &step_info.step;
+            for span in std::iter::once(step.name_span()).chain(step.hyps()) {
+                if let Some(new_name) = new_names.get(span.as_ref(&step_info.source)) {
+                    edits.push(TextEdit {
+                        range: self.span_range(step_idx, span),
+                        new_text: new_name.clone(),
+                    });
+                }
+            }
+        }
+        edits
+    }
+
+    /// Renames a step, and updates all hypothesis references to it.
+    pub fn rename_step(&self, old_name: &str, new_name: &str) -> Result<Vec<TextEdit>, String> {
+        if !self.steps_by_name.contains_key(old_name) {
+            return Err(format!("Unknown step {}", old_name));
+        }
+        if !is_valid_step_name(new_name) {
+            return Err(format!("Invalid step name {}", new_name));
+        }
+        if self.steps_by_name.contains_key(new_name) {
+            return Err(format!("Step {} already exists", new_name));
+        }
+        let mut new_names = HashMap::new();
+        new_names.insert(old_name, new_name.to_string());
+        Ok(self.rename_steps_edits(&new_names))
+    }
+
+    /// Renumbers all the steps in order, using the given increment,
+    /// and updates the hypothesis references accordingly.
+    /// The `qed` step keeps its name.
+    pub fn renumber(&self, increment: usize) -> Vec<TextEdit> {
+        let mut new_names = HashMap::new();
+        let mut number = 0;
+        for step_idx in 0..self.steps.len() {
+            let name = self.step_name(step_idx);
+            if name.is_empty() || name == "qed" {
+                continue;
+            }
+            number += increment;
+            if name != number.to_string() {
+                new_names.insert(name, number.to_string());
+            }
+        }
+        self.rename_steps_edits(&new_names)
+    }
+}
@@ -1,6 +1,7 @@
 //! Representation of a Proof Step
 use std::slice::Iter;
 
+use super::work_variables::WorkVariables;
 use super::worksheet::{Diag, Span, StepIdx};
 use super::ProofWorksheet;
 use lazy_static::lazy_static;
@@ -8,6 +9,7 @@ use memchr::memchr;
 use metamath_knife::formula::{Label, Substitutions};
 use metamath_knife::Database;
 use metamath_knife::Formula;
+use metamath_knife::StatementRef;
 use regex::Regex;
 
 /// The type of the step: hypothesis, normal proof step of QED step.
@@ -43,10 +45,10 @@ pub struct Step {
 }
 
 impl Step {
-    pub fn from_str(buf: &str, database: &Database) -> Step {
+    pub(crate) fn from_str(buf: &str, database: &Database, work_vars: &mut WorkVariables) -> Step {
         lazy_static! {
             static ref PROOF_LINE: Regex = Regex::new(
-                r"(?s)(h?)([0-9a-z]+):((?:\?|[0-9a-z]*)(?:,(?:\?|[0-9a-z]+))*):(\?|[0-9A-Za-z_\-\.]+)[ \t\n]+(.+)",
+                r"(?s)(h?)([0-9a-z]+):((?:\?|[0-9a-z]*)(?:,(?:\?|[0-9a-z]+))*):(\?|[0-9A-Za-z_\-\.]+)(?:[ \t\n]+(.+))?",
             ).expect("Malformed Regex");
         }
         let nset = database.name_result();
@@ -96,19 +98,19 @@ impl Step {
                     }
                 }
             };
-            let formula_caps = caps
-                .get(5)
-                .expect("Regex did not return right number of captures");
-            let formula_span = Some(formula_caps.into());
-            let formula_string = formula_caps.as_str();
+            // The formula may be missing, in which case it can be filled in by unification
+            let formula_caps = caps.get(5);
+            let formula_span = formula_caps.map(Span::from);
             // TODO check that the formula starts with the "provable" typecode token
-            let formula = match grammar.parse_string(formula_string, nset) {
-                Ok(formula) => Some(formula),
-                Err(diag) => {
-                    diags.push(diag.into());
-                    None
+            let formula = formula_caps.and_then(|formula_caps| {
+                match work_vars.parse_formula(formula_caps.as_str(), database) {
+                    Ok(formula) => Some(formula),
+                    Err(diag) => {
+                        diags.push(diag.into());
+                        None
+                    }
                 }
-            };
+            });
             Step {
                 name_span,
                 step_type,
@@ -162,6 +164,18 @@ impl Step {
         &self.hyps_span
     }
 
+    #[inline]
+    /// The span of the label of the theorem applied
+    pub(crate) fn label_span(&self) -> &Span {
+        &self.label_span
+    }
+
+    #[inline]
+    /// Whether this step is a hypothesis step
+    pub(crate) fn is_hyp(&self) -> bool {
+        matches!(self.step_type, StepType::Hyp)
+    }
+
     #[inline]
     #[must_use]
     /// The label of this step
@@ -188,9 +202,19 @@ impl Step {
     }
 
     #[inline]
-    /// Provides the range where the formula can be found
+    /// The span of the formula, if any
+    pub(crate) fn formula_span(&self) -> Option<&Span> {
+        self.formula_span.as_ref()
+    }
+
+    #[inline]
+    /// Provides the range where the formula can be found,
+    /// or the range of the label if this step has no formula
     pub(crate) fn formula_range(&self, offset: usize) -> std::ops::Range<usize> {
-        self.formula_span.as_ref().unwrap().as_range(offset)
+        self.formula_span
+            .as_ref()
+            .unwrap_or(&self.label_span)
+            .as_range(offset)
     }
 
     /// Checks that this step can be derived
@@ -228,7 +252,61 @@ impl Step {
         Ok(())
     }
 
-    fn check_unification(&self, step_idx: StepIdx, worksheet: &ProofWorksheet) -> Result<(), Diag> {
+    /// Checks whether the given assertion could be applied to derive this step:
+    /// its conclusion shall unify with this step's formula, and if hypotheses are already listed,
+    /// it shall have the same number of essential hypotheses.
+    pub(crate) fn can_apply(&self, stmt: StatementRef<'_>, db: &Database) -> bool {
+        let formula = match self.formula.as_ref() {
+            Some(formula) => formula,
+            None => return true,
+        };
+        let provable = db.grammar_result().provable_typecode();
+        let nset = db.name_result();
+        if stmt
+            .math_iter()
+            .next()
+            .and_then(|token| nset.lookup_symbol(token.slice))
+            .map(|symbol| symbol.atom)
+            != Some(provable)
+        {
+            return false;
+        }
+        let stmt_formula = match db.stmt_parse_result().get_formula(&stmt) {
+            Some(stmt_formula) => stmt_formula,
+            None => return false,
+        };
+        if !self.hyps.is_empty() {
+            match db.scope_result().get(stmt.label()) {
+                Some(frame) if frame.as_ref(db).essentials().count() == self.hyps.len() => {}
+                _ => return false,
+            }
+        }
+        formula
+            .unify(stmt_formula, &mut Substitutions::new())
+            .is_ok()
+    }
+
+    /// Checks that this step can be derived from its hypotheses, using the assertion given by its label.
+    ///
+    /// Work variables in this step or in its hypotheses are bound where the assertion has a variable,
+    /// and the bindings required for the unification to succeed are returned.
+    pub(crate) fn check_unification(
+        &self,
+        step_idx: StepIdx,
+        worksheet: &ProofWorksheet,
+    ) -> Result<Substitutions, Diag> {
+        self.unify_with_hyps(step_idx, worksheet, &mut Substitutions::new())
+    }
+
+    /// Unifies this step and its hypotheses with the assertion given by its label,
+    /// like [`Self::check_unification`], and collects the substitutions for the assertion's variables.
+    /// The substitutions found are kept even if unification fails.
+    pub(crate) fn unify_with_hyps(
+        &self,
+        step_idx: StepIdx,
+        worksheet: &ProofWorksheet,
+        substitutions: &mut Substitutions,
+    ) -> Result<Substitutions, Diag> {
         let formula = worksheet.step_stmt_formula(step_idx)?;
         let label_name = worksheet.step_label(step_idx);
         let frame = worksheet.db.scope_result().get(label_name).unwrap();
@@ -239,37 +317,52 @@ impl Step {
                 actual: self.hyps.len(),
             });
         }
-        let mut substitutions = Substitutions::new();
+        let mut bindings = Substitutions::new();
         self.formula
             .as_ref()
             .ok_or(Diag::NoFormula)?
-            .unify(formula, &mut substitutions)?;
+            .unify(formula, substitutions)?;
         for (hyp_idx, (_, formula)) in essentials.into_iter().enumerate() {
             let step_name = worksheet.hyp_name(step_idx, hyp_idx);
-            if let Some(&hyp_step_idx) = worksheet.steps_by_name.get(step_name) {
+            if step_name == "?" {
+                // Unknown hypothesis, to be filled in by unification
+                continue;
+            } else if let Some(&hyp_step_idx) = worksheet.steps_by_name.get(step_name) {
                 if let Some(hyp_formula) = worksheet.step_formula(hyp_step_idx) {
                     let mut hyp_subst = Substitutions::new();
                     hyp_formula
                         .unify(formula, &mut hyp_subst)
                         .map_err(|_| Diag::UnificationFailedForHyp(hyp_idx))?;
-                    Self::check_and_extend(&mut substitutions, &hyp_subst, hyp_idx)?;
+                    Self::check_and_extend(
+                        substitutions,
+                        &hyp_subst,
+                        hyp_idx,
+                        &worksheet.work_vars,
+                        &mut bindings,
+                    )?;
                 }
             } else {
                 return Err(Diag::UnknownStepName(self.hyps[hyp_idx].as_range(0)));
             }
         }
-        Ok(())
+        Ok(bindings)
     }
 
     // TODO - move this check that those substitutions are compatible to metamath_knife!!
+    /// Differing substitutions are still compatible if work variables can be bound to make them match.
     fn check_and_extend(
         s1: &mut Substitutions,
         s2: &Substitutions,
         hyp_idx: usize,
+        work_vars: &WorkVariables,
+        bindings: &mut Substitutions,
     ) -> Result<(), Diag> {
         for (&label, f1) in s1.into_iter() {
             if let Some(f2) = s2.get(label) {
-                if f1 != f2 {
+                if f1 != f2
+                    && !work_vars.bind(f1, f2, bindings)
+                    && !work_vars.bind(f2, f1, bindings)
+                {
                     return Err(Diag::UnificationFailedForHyp(hyp_idx));
                 }
             }
@@ -0,0 +1,294 @@
+//! Unification of a proof worksheet
+//! Fills in the hypotheses and formulas which can be deduced from the other steps.
+
+use super::worksheet::{Diag, Span, StepIdx};
+use super::ProofWorksheet;
+use lsp_types::{Range as LspRange, TextEdit};
+use metamath_knife::formula::Substitutions;
+use metamath_knife::scopeck::Hyp;
+use metamath_knife::statement::as_str;
+use metamath_knife::{Formula, StatementRef};
+
+/// Checks that two sets of substitutions do not assign different formulas to the same variable
+pub(super) fn compatible(s1: &Substitutions, s2: &Substitutions) -> bool {
+    s1.into_iter()
+        .all(|(&label, f1)| s2.get(label).map_or(true, |f2| f1 == f2))
+}
+
+/// Replaces the work variables bound in `bindings` within the given formula.
+/// Other variables are kept as they are.
+fn instantiate(formula: &Formula, bindings: &Substitutions) -> Formula {
+    let mut substitutions = Substitutions::new();
+    if formula.unify(formula, &mut substitutions).is_err() {
+        return formula.clone();
+    }
+    substitutions.extend(bindings);
+    formula.substitute(&substitutions)
+}
+
+/// The unification of a step with the assertion it applies, as shown when hovering its label
+pub(crate) struct StepUnification {
+    /// The variables of the assertion, with the expression substituted for each, if known
+    pub(crate) substitutions: Vec<(String, Option<String>)>,
+    /// The formulas expected for the hypotheses of the step, after substitution
+    pub(crate) expected_hyps: Vec<String>,
+    /// Why unification failed, if it did, and the index of the hypothesis concerned, if any
+    pub(crate) error: Option<(String, Option<usize>)>,
+}
+
+impl ProofWorksheet {
+    /// Unifies a step with the assertion it applies, and renders the substitutions
+    /// of the assertion's variables, as well as the expected hypotheses.
+    /// Returns `None` for hypothesis steps, or if the assertion is unknown.
+    pub(crate) fn step_unification(&self, step_idx: StepIdx) -> Option<StepUnification> {
+        let step = &self.steps[step_idx].step;
+        if step.is_hyp() {
+            return None;
+        }
+        let sref = self.db.statement(self.step_label(step_idx))?;
+        let frame = self.db.scope_result().get(sref.label())?;
+        let nset = self.db.name_result();
+        let mut substitutions = Substitutions::new();
+        let error = step
+            .unify_with_hyps(step_idx, self, &mut substitutions)
+            .err()
+            .map(|diag| {
+                let hyp_idx = match diag {
+                    Diag::UnificationFailedForHyp(hyp_idx) => Some(hyp_idx),
+                    _ => None,
+                };
+                (diag.message(), hyp_idx)
+            });
+        let variables = frame
+            .hypotheses
+            .iter()
+            .filter_map(|hyp| match hyp {
+                Hyp::Floating(sa, ..) => {
+                    let float = self.db.statement_by_address(*sa);
+                    let variable = as_str(float.math_iter().nth(1)?.slice).to_string();
+                    let label = nset.lookup_label(float.label())?.atom;
+                    let expression = substitutions
+                        .get(label)
+                        .map(|formula| self.expression_string(formula));
+                    Some((variable, expression))
+                }
+                Hyp::Essential(..) => None,
+            })
+            .collect();
+        let expected_hyps = frame
+            .as_ref(&self.db)
+            .essentials()
+            .map(|(_, formula)| self.formula_string(&instantiate(formula, &substitutions)))
+            .collect();
+        Some(StepUnification {
+            substitutions: variables,
+            expected_hyps,
+            error,
+        })
+    }
+
+    /// Builds an edit replacing the given span of a step with a new text
+    pub(super) fn replace_edit(
+        &self,
+        step_idx: StepIdx,
+        span: &Span,
+        new_text: String,
+    ) -> TextEdit {
+        TextEdit {
+            range: self.span_range(step_idx, span),
+            new_text,
+        }
+    }
+
+    /// Builds an edit inserting the given formula after the label of a step
+    fn insert_formula_edit(&self, step_idx: StepIdx, formula: &Formula) -> TextEdit {
+        let step_info = &self.steps[step_idx];
+        let label_end = step_info.step.label_span().as_range(step_info.byte_idx).end;
+        let position = self.byte_to_lsp_position(label_end);
+        TextEdit {
+            range: LspRange {
+                start: position,
+                end: position,
+            },
+            new_text: format!(" {}", self.formula_string(formula)),
+        }
+    }
+
+    /// Builds an edit replacing the whole formula of a step
+    pub(super) fn replace_formula_edit(&self, step_idx: StepIdx, formula: &Formula) -> TextEdit {
+        let step_info = &self.steps[step_idx];
+        let range = step_info.step.formula_range(0);
+        // The formula span extends to the end of the step, including the last line break
+        let text = step_info.source[range.clone()].trim_end();
+        let span = Span::new(range.start, text.len());
+        self.replace_edit(step_idx, &span, self.formula_string(formula))
+    }
+
+    /// Collects the bindings of work variables required by the unification of each step
+    fn work_variable_bindings(&self) -> Substitutions {
+        let mut bindings = Substitutions::new();
+        for (step_idx, step_info) in self.steps.iter().enumerate() {
+            if step_info.step.is_hyp() {
+                continue;
+            }
+            if let Ok(step_bindings) = step_info.step.check_unification(step_idx, self) {
+                if compatible(&bindings, &step_bindings) {
+                    bindings.extend(&step_bindings);
+                }
+            }
+        }
+        bindings
+    }
+
+    /// Checks whether all the variables of the conclusion of the given assertion
+    /// are assigned by the substitutions
+    pub(super) fn is_determined(
+        &self,
+        sref: StatementRef<'_>,
+        substitutions: &Substitutions,
+    ) -> bool {
+        let nset = self.db.name_result();
+        let frame = match self.db.scope_result().get(sref.label()) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        frame.hypotheses.iter().all(|hyp| match hyp {
+            Hyp::Floating(sa, ..) => {
+                let float = self.db.statement_by_address(*sa);
+                let used = float
+                    .math_iter()
+                    .nth(1)
+                    .map_or(false, |var| sref.math_iter().any(|t| t.slice == var.slice));
+                !used
+                    || nset
+                        .lookup_label(float.label())
+                        .map_or(false, |label| substitutions.get(label.atom).is_some())
+            }
+            Hyp::Essential(..) => true,
+        })
+    }
+
+    /// Unifies this worksheet, and returns the edits to be applied to its source text.
+    ///
+    /// Work variables bound by the unification of any step are first instantiated
+    /// in all formulas of the worksheet.
+    /// Steps are then handled in order, so that formulas filled in for a step
+    /// can be used for the subsequent steps. For each step applying a known assertion:
+    /// - `?` or missing hypothesis references are replaced by the only previous step
+    ///   matching the corresponding essential hypothesis, if there is exactly one,
+    /// - a missing formula is derived from the assertion, once all of its variables are
+    ///   determined by the hypotheses.
+    pub fn unify(&self) -> Vec<TextEdit> {
+        let bindings = self.work_variable_bindings();
+        let mut edits = vec![];
+        let mut formulas: Vec<Option<Formula>> = Vec::with_capacity(self.steps.len());
+        for (step_idx, step_info) in self.steps.iter().enumerate() {
+            let formula = step_info.step.formula().map(|formula| {
+                let instance = instantiate(formula, &bindings);
+                if self.formula_string(&instance) != self.formula_string(formula) {
+                    edits.push(self.replace_formula_edit(step_idx, &instance));
+                }
+                instance
+            });
+            formulas.push(formula);
+        }
+        for step_idx in 0..self.steps.len() {
+            let step = &self.steps[step_idx].step;
+            let sref = match self.db.statement(self.step_label(step_idx)) {
+                Some(sref) => sref,
+                None => continue,
+            };
+            let stmt_formula = match self.db.stmt_parse_result().get_formula(&sref) {
+                Some(stmt_formula) => stmt_formula,
+                None => continue,
+            };
+
+            // Hypothesis steps simply take the formula of the database hypothesis
+            if step.is_hyp() {
+                if formulas[step_idx].is_none() {
+                    edits.push(self.insert_formula_edit(step_idx, stmt_formula));
+                    formulas[step_idx] = Some(stmt_formula.clone());
+                }
+                continue;
+            }
+
+            let frame = match self.db.scope_result().get(sref.label()) {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let essentials: Vec<_> = frame.as_ref(&self.db).essentials().collect();
+            if step.hyps().len() > essentials.len() {
+                continue;
+            }
+            let mut substitutions = Substitutions::new();
+            if let Some(formula) = &formulas[step_idx] {
+                if formula.unify(stmt_formula, &mut substitutions).is_err() {
+                    continue;
+                }
+            }
+
+            // Known hypotheses constrain the substitutions
+            let mut hyp_names: Vec<String> = (0..essentials.len())
+                .map(|hyp_idx| {
+                    if hyp_idx < step.hyps().len() {
+                        self.hyp_name(step_idx, hyp_idx).to_string()
+                    } else {
+                        "?".to_string()
+                    }
+                })
+                .collect();
+            let mut hyps_changed = hyp_names.len() != step.hyps().len();
+            for (hyp_name, (_, hyp_formula)) in hyp_names.iter().zip(essentials.iter()) {
+                if let Some(Some(formula)) = self.steps_by_name.get(hyp_name).map(|&i| &formulas[i])
+                {
+                    let mut hyp_subst = Substitutions::new();
+                    if formula.unify(hyp_formula, &mut hyp_subst).is_ok()
+                        && compatible(&substitutions, &hyp_subst)
+                    {
+                        substitutions.extend(&hyp_subst);
+                    }
+                }
+            }
+
+            // Unknown hypotheses are searched among the previous steps.
+            // Each hypothesis found further constrains the substitutions,
+            // so this is repeated until no more hypotheses can be found.
+            let mut found = true;
+            while found {
+                found = false;
+                for (hyp_idx, (_, hyp_formula)) in essentials.iter().enumerate() {
+                    if hyp_names[hyp_idx] != "?" {
+                        continue;
+                    }
+                    let candidates: Vec<_> = (0..step_idx)
+                        .filter_map(|candidate_idx| {
+                            let formula = formulas[candidate_idx].as_ref()?;
+                            let mut hyp_subst = Substitutions::new();
+                            formula.unify(hyp_formula, &mut hyp_subst).ok()?;
+                            compatible(&substitutions, &hyp_subst)
+                                .then(|| (candidate_idx, hyp_subst))
+                        })
+                        .take(2)
+                        .collect();
+                    if let [(candidate_idx, hyp_subst)] = candidates.as_slice() {
+                        hyp_names[hyp_idx] = self.step_name(*candidate_idx).to_string();
+                        substitutions.extend(hyp_subst);
+                        hyps_changed = true;
+                        found = true;
+                    }
+                }
+            }
+            if hyps_changed {
+                edits.push(self.replace_edit(step_idx, step.hyps_span(), hyp_names.join(",")));
+            }
+
+            // Finally, the formula can be derived if all variables are known
+            if formulas[step_idx].is_none() && self.is_determined(sref, &substitutions) {
+                let formula = stmt_formula.substitute(&substitutions);
+                edits.push(self.insert_formula_edit(step_idx, &formula));
+                formulas[step_idx] = Some(formula);
+            }
+        }
+        edits
+    }
+}
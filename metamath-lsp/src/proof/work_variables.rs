@@ -0,0 +1,251 @@
+//! Work variables
+//!
+//! Work variables, like `&W1`, `&C1` or `&S1`, stand for formulas which are not known yet
+//! in a proof worksheet. They are bound to actual formulas through unification.
+//!
+//! Since the database's name set cannot be extended, each work variable is represented
+//! by a database variable of the same typecode, which is neither a variable of the theorem being proven
+//! nor a variable written in any step of the worksheet.
+//! Database variables are allocated starting from the last ones declared, which are the least likely
+//! to be used otherwise in the proof. The number of work variables of a typecode is therefore
+//! limited by the number of spare database variables of that typecode.
+
+use metamath_knife::diag::StmtParseError;
+use metamath_knife::formula::{Label, Substitutions};
+use metamath_knife::nameck::Atom;
+use metamath_knife::statement::as_str;
+use metamath_knife::{Database, Formula, Span, StatementType};
+use std::collections::{HashMap, HashSet};
+
+/// The work variables of a proof worksheet
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WorkVariables {
+    /// The database variable symbol and floating hypothesis label representing each work variable
+    by_name: HashMap<String, (Atom, Label)>,
+    /// The work variable represented by each database variable symbol
+    by_symbol: HashMap<Atom, String>,
+    /// The work variable represented by each floating hypothesis label
+    by_label: HashMap<Label, String>,
+    /// The database variables which shall not be used to represent work variables
+    excluded: HashSet<Atom>,
+    /// Whether a database variable representing a work variable has been excluded since,
+    /// in which case the formulas shall be parsed again
+    conflict: bool,
+    /// The database variables and their floating hypothesis labels, by typecode,
+    /// in the order of their declaration. This is built when the first work variable is allocated.
+    floating_hyps: HashMap<Atom, Vec<(Atom, Label)>>,
+}
+
+/// Lists the database variables and their floating hypothesis labels, by typecode
+fn floating_hyps(db: &Database) -> HashMap<Atom, Vec<(Atom, Label)>> {
+    let nset = db.name_result();
+    let mut floating_hyps: HashMap<Atom, Vec<(Atom, Label)>> = HashMap::new();
+    for stmt in db.statements() {
+        if stmt.statement_type() != StatementType::Floating {
+            continue;
+        }
+        let mut math = stmt.math_iter();
+        let typecode = math
+            .next()
+            .and_then(|token| nset.lookup_symbol(token.slice));
+        let symbol = math
+            .next()
+            .and_then(|token| nset.lookup_symbol(token.slice));
+        let label = nset.lookup_label(stmt.label());
+        if let (Some(typecode), Some(symbol), Some(label)) = (typecode, symbol, label) {
+            floating_hyps
+                .entry(typecode.atom)
+                .or_default()
+                .push((symbol.atom, label.atom));
+        }
+    }
+    floating_hyps
+}
+
+/// Returns the typecode of the given work variable name, or `None` if it is not a work variable name
+pub(crate) fn work_variable_typecode(name: &str) -> Option<&'static [u8]> {
+    let mut chars = name.strip_prefix('&')?.chars();
+    let typecode: &[u8] = match chars.next()? {
+        'W' => b"wff",
+        'C' => b"class",
+        'S' => b"setvar",
+        _ => return None,
+    };
+    let number = chars.as_str();
+    (!number.is_empty() && number.chars().all(|c| c.is_ascii_digit())).then(|| typecode)
+}
+
+/// Maps a byte offset in a rewritten formula string back to the original formula string.
+/// Each shift is the offset following a replaced token, in the rewritten and the original strings.
+fn original_offset(shifts: &[(usize, usize)], offset: usize) -> usize {
+    match shifts
+        .iter()
+        .rev()
+        .find(|(rewritten, _)| *rewritten <= offset)
+    {
+        Some((rewritten, original)) => original + (offset - rewritten),
+        None => offset,
+    }
+}
+
+/// Maps the span of a statement parse error back to the original formula string
+fn map_error_span(err: StmtParseError, shifts: &[(usize, usize)]) -> StmtParseError {
+    let map = |span: Span| {
+        Span::new(
+            original_offset(shifts, span.start as usize),
+            original_offset(shifts, span.end as usize),
+        )
+    };
+    match err {
+        StmtParseError::UnknownToken(span) => StmtParseError::UnknownToken(map(span)),
+        StmtParseError::UnparseableStatement(span) => {
+            StmtParseError::UnparseableStatement(map(span))
+        }
+        StmtParseError::ParsedStatementTooShort(span, typecode) => {
+            StmtParseError::ParsedStatementTooShort(map(span), typecode)
+        }
+        err => err,
+    }
+}
+
+impl WorkVariables {
+    /// Excludes the given database variables from being used to represent work variables,
+    /// typically the variables of the theorem being proven.
+    pub(crate) fn exclude(&mut self, symbols: impl Iterator<Item = Atom>) {
+        for symbol in symbols {
+            self.conflict |= self.by_symbol.contains_key(&symbol);
+            self.excluded.insert(symbol);
+        }
+    }
+
+    /// Excludes the database variables written in the given formula of a worksheet
+    /// from being used to represent work variables.
+    pub(crate) fn exclude_text(&mut self, text: &str, db: &Database) {
+        let nset = db.name_result();
+        self.exclude(
+            text.split_ascii_whitespace()
+                .filter_map(|token| nset.lookup_symbol(token.as_bytes()))
+                .map(|lookup| lookup.atom),
+        );
+    }
+
+    /// Forgets all the excluded database variables, before they are collected again from the worksheet
+    pub(crate) fn clear_excluded(&mut self) {
+        self.excluded.clear();
+    }
+
+    /// Returns whether a database variable representing a work variable has been excluded
+    /// since the last call. In that case, the work variables shall be released
+    /// and all the formulas parsed again.
+    pub(crate) fn take_conflict(&mut self) -> bool {
+        std::mem::take(&mut self.conflict)
+    }
+
+    /// Releases all the work variables, so that they are allocated again when parsing formulas.
+    /// The excluded database variables are kept.
+    pub(crate) fn release(&mut self) {
+        self.by_name.clear();
+        self.by_symbol.clear();
+        self.by_label.clear();
+    }
+
+    /// Returns the database variable and floating hypothesis label representing the given work variable,
+    /// allocating one if needed.
+    fn get_or_allocate(&mut self, name: &str, db: &Database) -> Option<(Atom, Label)> {
+        if let Some(&variable) = self.by_name.get(name) {
+            return Some(variable);
+        }
+        let typecode = db
+            .name_result()
+            .lookup_symbol(work_variable_typecode(name)?)?
+            .atom;
+        if self.floating_hyps.is_empty() {
+            self.floating_hyps = floating_hyps(db);
+        }
+        let (symbol, label) = self
+            .floating_hyps
+            .get(&typecode)?
+            .iter()
+            .rev()
+            .find(|(symbol, _)| {
+                !self.by_symbol.contains_key(symbol) && !self.excluded.contains(symbol)
+            })
+            .copied()?;
+        self.by_name.insert(name.to_string(), (symbol, label));
+        self.by_symbol.insert(symbol, name.to_string());
+        self.by_label.insert(label, name.to_string());
+        Some((symbol, label))
+    }
+
+    /// The work variable name represented by the given database variable symbol, if any
+    pub(crate) fn name(&self, symbol: Atom) -> Option<&str> {
+        self.by_symbol.get(&symbol).map(String::as_str)
+    }
+
+    /// Whether the given floating hypothesis label represents a work variable
+    pub(crate) fn is_work_variable(&self, label: Label) -> bool {
+        self.by_label.contains_key(&label)
+    }
+
+    /// Parses a step formula, which may contain work variables.
+    /// The database variables written in the formula are excluded first.
+    /// Work variables are replaced by the database variables representing them before parsing,
+    /// and the spans of any parse error are mapped back to the original string.
+    pub(crate) fn parse_formula(
+        &mut self,
+        formula: &str,
+        db: &Database,
+    ) -> Result<Formula, StmtParseError> {
+        self.exclude_text(formula, db);
+        let grammar = db.grammar_result();
+        let nset = db.name_result();
+        if !formula.contains('&') {
+            return grammar.parse_string(formula, nset);
+        }
+        let mut rewritten = String::new();
+        let mut shifts = vec![];
+        let mut last = 0;
+        for token in formula.split_ascii_whitespace() {
+            let start = token.as_ptr() as usize - formula.as_ptr() as usize;
+            if let Some((symbol, _)) = self.get_or_allocate(token, db) {
+                rewritten.push_str(&formula[last..start]);
+                rewritten.push_str(as_str(nset.atom_name(symbol)));
+                last = start + token.len();
+                shifts.push((rewritten.len(), last));
+            }
+        }
+        rewritten.push_str(&formula[last..]);
+        grammar
+            .parse_string(&rewritten, nset)
+            .map_err(|err| map_error_span(err, &shifts))
+    }
+
+    /// Attempts to bind the work variables of `pattern` so that it matches `concrete`,
+    /// adding the new bindings to `bindings`.
+    /// Any other variable of the pattern may only match itself.
+    pub(crate) fn bind(
+        &self,
+        concrete: &Formula,
+        pattern: &Formula,
+        bindings: &mut Substitutions,
+    ) -> bool {
+        if self.by_label.is_empty() {
+            return false;
+        }
+        let mut substitutions = Substitutions::new();
+        if concrete.unify(pattern, &mut substitutions).is_err() {
+            return false;
+        }
+        for (&label, formula) in &substitutions {
+            if self.is_work_variable(label) {
+                if bindings.get(label).map_or(false, |bound| bound != formula) {
+                    return false;
+                }
+            } else if formula.get_by_path(&[]) != Some(label) {
+                return false;
+            }
+        }
+        bindings.extend(&substitutions);
+        true
+    }
+}
@@ -1,4 +1,6 @@
 use crate::proof::step::Step;
+use crate::proof::work_variables::WorkVariables;
+use crate::rope_ext::{byte_to_utf16_idx, utf16_to_byte_idx};
 use lazy_static::lazy_static;
 use lsp_types::{
     Diagnostic as LspDiagnostic, DiagnosticSeverity, Position, Range as LspRange,
@@ -6,12 +8,12 @@ use lsp_types::{
 };
 use metamath_knife::diag::StmtParseError;
 use metamath_knife::formula::UnificationError;
-use metamath_knife::statement::{StatementAddress, TokenPtr};
+use metamath_knife::statement::{as_str, StatementAddress, TokenPtr};
 use metamath_knife::{Database, Formula, StatementRef};
 use regex::{Match, Regex};
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::ops::{Index, Range};
+use std::ops::{Bound, Index, Range};
 
 /// A Diagnostic
 #[derive(Clone, Debug)]
@@ -88,7 +90,7 @@ impl From<&Span> for Range<usize> {
 }
 
 impl Diag {
-    fn message(&self) -> String {
+    pub(crate) fn message(&self) -> String {
         match self {
             Diag::UnknownStepName(_) => "Unknown step name".to_string(),
             Diag::UnknownTheoremLabel(_) => "Unknown theorem".to_string(),
@@ -154,7 +156,7 @@ pub(crate) type StepIdx = usize;
 
 /// Information relative to a step
 /// The "source" string of each step is cloned to be stored in the step info.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct StepInfo {
     pub(crate) byte_idx: usize,
     pub(crate) line_idx: usize,
@@ -218,20 +220,24 @@ fn line_count(s: &str) -> usize {
 
 /// This structure is used to display a Metamath proof in the form of an MMP file:
 /// A list of steps with the theorems and hypotheses used to derive each.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct ProofWorksheet {
     /// The database used to build this worksheet
     pub(crate) db: Database,
+    /// The label of the theorem being proven, as given by `THEOREM=`
+    pub(crate) theorem_label: String,
     /// The statement which is being proven
     pub(crate) sadd: Option<StatementAddress>,
     /// A position in the database. Only statements before this one are allowed in a proof.
-    loc_after: Option<StatementAddress>,
+    pub(crate) loc_after: Option<StatementAddress>,
     /// Top line and first comment
     top: String,
     /// All the steps in this proof, in the order they appear
     pub(crate) steps: Vec<StepInfo>,
     /// The indices of the steps in this proof, referenced by their proof label (usually these are actually numbers, but any valid metamath label is allowed)
     pub(crate) steps_by_name: HashMap<String, StepIdx>,
+    /// The work variables used in this proof
+    pub(crate) work_vars: WorkVariables,
 }
 
 impl Index<&str> for ProofWorksheet {
@@ -292,12 +298,21 @@ impl ProofWorksheet {
         } else {
             (0, 0, &self.top)
         };
-        let line_idx = line_count(&source[0..byte_idx - start_byte_idx]);
-        let line_start_idx =
-            memchr::memrchr(b'\n', source[0..byte_idx - start_byte_idx].as_bytes()).unwrap_or(0);
+        let before = &source[0..byte_idx - start_byte_idx];
+        let line_idx = line_count(before);
+        let line_start_idx = memchr::memrchr(b'\n', before.as_bytes()).map_or(0, |idx| idx + 1);
         Position {
             line: (start_line_idx + line_idx) as u32,
-            character: (byte_idx - line_start_idx - start_byte_idx) as u32,
+            character: byte_to_utf16_idx(&before[line_start_idx..], before.len() - line_start_idx),
+        }
+    }
+
+    /// The LSP range of the given span of a step
+    pub(crate) fn span_range(&self, step_idx: StepIdx, span: &Span) -> LspRange {
+        let range = span.as_range(self.steps[step_idx].byte_idx);
+        LspRange {
+            start: self.byte_to_lsp_position(range.start),
+            end: self.byte_to_lsp_position(range.end),
         }
     }
 
@@ -317,7 +332,8 @@ impl ProofWorksheet {
             byte_idx += memchr::memchr(b'\n', source[byte_idx..].as_bytes()).unwrap_or(0) + 1;
             line_idx += 1;
         }
-        start_byte_idx + byte_idx + position.character as usize
+        let line = source.get(byte_idx..).unwrap_or_default();
+        start_byte_idx + byte_idx + utf16_to_byte_idx(line, position.character)
     }
 
     /// Returns the (zero-based) line
@@ -345,7 +361,7 @@ impl ProofWorksheet {
     /// Returns the step containing the given position,
     /// As well as the index of that position relative to the span
     #[inline]
-    fn step_at(&self, position: Position) -> (Option<StepIdx>, usize) {
+    pub(crate) fn step_at(&self, position: Position) -> (Option<StepIdx>, usize) {
         let step_idx = self
             .steps
             .binary_search_by(|s| s.line_idx.cmp(&(position.line as usize)))
@@ -361,7 +377,11 @@ impl ProofWorksheet {
             byte_idx += memchr::memchr(b'\n', source[byte_idx..].as_bytes()).unwrap_or(0) + 1;
             line_idx += 1;
         }
-        (step_idx, byte_idx + position.character as usize)
+        let line = source.get(byte_idx..).unwrap_or_default();
+        (
+            step_idx,
+            byte_idx + utf16_to_byte_idx(line, position.character),
+        )
     }
 
     /// Apply the changes from the provided LSP event.
@@ -417,7 +437,7 @@ impl ProofWorksheet {
             let step_line_count = line_count(&new_text[..step_len]);
             let step_end = find_step_end(new_text[..step_len].as_bytes()).unwrap_or(step_len);
             let source = new_text[..step_len].to_owned();
-            let step = Step::from_str(&new_text[..step_end], &self.db);
+            let step = Step::from_str(&new_text[..step_end], &self.db, &mut self.work_vars);
             add_steps.push(StepInfo {
                 byte_idx,
                 line_idx,
@@ -455,6 +475,16 @@ impl ProofWorksheet {
 
         // Finally, we can replace the new steps into our reference
         self.steps.splice(old_step_range, add_steps);
+        self.update_excluded_variables();
+
+        // If a variable now written in the worksheet was representing a work variable,
+        // all the steps need to be parsed again with new work variables
+        let start_step_idx = if self.work_vars.take_conflict() {
+            self.reparse_steps();
+            0
+        } else {
+            start_step_idx
+        };
 
         // And we update step names and validate all dependent steps
         for step_idx in start_step_idx..self.steps.len() {
@@ -466,6 +496,32 @@ impl ProofWorksheet {
         }
     }
 
+    /// Collects again the database variables which shall not represent work variables:
+    /// the variables of the theorem being proven, and those written in the step formulas.
+    /// Variables which are no longer written in any formula may then represent work variables again.
+    fn update_excluded_variables(&mut self) {
+        self.work_vars.clear_excluded();
+        if let Some(frame) = self.db.scope_result().get(self.theorem_label.as_bytes()) {
+            self.work_vars.exclude(frame.var_list.iter().copied());
+        }
+        for step_info in &self.steps {
+            if let Some(span) = step_info.step.formula_span() {
+                self.work_vars
+                    .exclude_text(span.as_ref(&step_info.source), &self.db);
+            }
+        }
+    }
+
+    /// Parses all the steps again, allocating their work variables anew
+    fn reparse_steps(&mut self) {
+        self.work_vars.release();
+        for step_info in &mut self.steps {
+            let source = &step_info.source;
+            let step_end = find_step_end(source.as_bytes()).unwrap_or(source.len());
+            step_info.step = Step::from_str(&source[..step_end], &self.db, &mut self.work_vars);
+        }
+    }
+
     // /// Creates a new proof worksheet with the given source text
     // fn new(db: &Database, source: String) -> Self {
     //     let mut worksheet = ProofWorksheet {
@@ -505,25 +561,30 @@ impl ProofWorksheet {
     //     worksheet
     // }
 
+    /// Builds the LSP diagnostic for a diagnostic of the given step
+    pub(crate) fn lsp_diagnostic(&self, step_info: &StepInfo, diag: &Diag) -> LspDiagnostic {
+        let range = diag.get_range(step_info);
+        LspDiagnostic {
+            range: LspRange {
+                start: self.byte_to_lsp_position(range.start),
+                end: self.byte_to_lsp_position(range.end),
+            },
+            severity: diag.severity(),
+            code: None,
+            code_description: None,
+            source: None,
+            message: diag.message(),
+            related_information: None,
+            tags: None,
+            data: None,
+        }
+    }
+
     pub fn diagnostics(&self) -> Vec<LspDiagnostic> {
         let mut diagnostics = vec![];
         for step_info in self.steps.iter() {
             for diag in step_info.step.diags() {
-                let range = diag.get_range(step_info);
-                diagnostics.push(LspDiagnostic {
-                    range: LspRange {
-                        start: self.byte_to_lsp_position(range.start),
-                        end: self.byte_to_lsp_position(range.end),
-                    },
-                    severity: diag.severity(),
-                    code: None,
-                    code_description: None,
-                    source: None,
-                    message: diag.message(),
-                    related_information: None,
-                    tags: None,
-                    data: None,
-                });
+                diagnostics.push(self.lsp_diagnostic(step_info, diag));
             }
         }
         diagnostics
@@ -563,6 +624,86 @@ impl ProofWorksheet {
             .ok_or_else(unknown_theorem)
     }
 
+    /// Renders the given formula as it shall be written in a proof step,
+    /// starting with the provable typecode
+    pub(crate) fn formula_string(&self, formula: &Formula) -> String {
+        let nset = self.db.name_result();
+        format!(
+            "{} {}",
+            as_str(nset.atom_name(self.db.grammar_result().provable_typecode())),
+            self.expression_string(formula)
+        )
+    }
+
+    /// Renders the symbols of the given formula, without any typecode,
+    /// as substituted for a variable
+    pub(crate) fn expression_string(&self, formula: &Formula) -> String {
+        let nset = self.db.name_result();
+        formula
+            .as_ref(&self.db)
+            .iter()
+            .map(|symbol| {
+                self.work_vars
+                    .name(symbol)
+                    .unwrap_or_else(|| as_str(nset.atom_name(symbol)))
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The comment of the theorem being proven, i.e. the text following the first `*` in the header
+    pub(crate) fn comment(&self) -> Option<&str> {
+        let start = self.top.find("\n*")? + 2;
+        Some(self.top[start..].trim())
+    }
+
+    /// The variables of the `$d` lines of this worksheet, one list for each line
+    pub(crate) fn distinct_vars(&self) -> Vec<Vec<&str>> {
+        std::iter::once(&self.top)
+            .chain(self.steps.iter().map(|step_info| &step_info.source))
+            .flat_map(|source| source.lines())
+            .filter_map(|line| line.strip_prefix("$d"))
+            .filter(|vars| vars.starts_with(|c: char| c.is_ascii_whitespace()))
+            .map(|vars| {
+                vars.split_ascii_whitespace()
+                    .filter(|var| *var != "$.")
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The bound of the statements which may be used in this proof:
+    /// up to the `LOC_AFTER` statement if one is given, or before the proven theorem otherwise.
+    pub(crate) fn scope_end(&self) -> Bound<StatementAddress> {
+        match (self.loc_after, self.sadd) {
+            (Some(loc_after), _) => Bound::Included(loc_after),
+            (None, Some(sadd)) => Bound::Excluded(sadd),
+            (None, None) => Bound::Unbounded,
+        }
+    }
+
+    /// Returns the label given by `THEOREM=` or `LOC_AFTER=` in the header,
+    /// if the given position is on one of them
+    pub(crate) fn header_label_at(&self, position: Position) -> Option<&str> {
+        if position.line != 0 {
+            return None;
+        }
+        let eol = memchr::memchr(b'\n', self.top.as_bytes()).unwrap_or(self.top.len());
+        let first_line = &self.top[0..eol];
+        let offset = utf16_to_byte_idx(first_line, position.character);
+        ["THEOREM=", "LOC_AFTER="]
+            .iter()
+            .find_map(|key| {
+                let start = first_line.find(key)? + key.len();
+                let end = first_line[start..]
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .map_or(first_line.len(), |len| start + len);
+                // The cursor may be just after the last character of the label
+                (start <= offset && offset <= end).then(|| &first_line[start..end])
+            })
+            .filter(|label| *label != "?")
+    }
+
     // First line has changed, update theorem name, loc_after
     fn update_first_line(&mut self) -> Option<()> {
         lazy_static! {
@@ -575,10 +716,15 @@ impl ProofWorksheet {
         FIRST_LINE.captures(first_line).map(|caps| {
             let statement_name = caps.get(1).unwrap().as_str();
             let loc_after_name = caps.get(2).unwrap().as_str();
+            self.theorem_label = statement_name.to_string();
             self.sadd = self
                 .db
                 .statement(statement_name.as_bytes())
                 .map(StatementRef::address);
+            if let Some(frame) = self.db.scope_result().get(statement_name.as_bytes()) {
+                // Work variables shall not be represented by the theorem's own variables
+                self.work_vars.exclude(frame.var_list.iter().copied());
+            }
             self.loc_after = self
                 .db
                 .statement(loc_after_name.as_bytes())
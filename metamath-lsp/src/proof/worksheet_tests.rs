@@ -1,10 +1,10 @@
 use lsp_types::{
     Diagnostic as LspDiagnostic, DiagnosticSeverity, Position, Range as LspRange,
-    TextDocumentContentChangeEvent,
+    TextDocumentContentChangeEvent, TextEdit,
 };
 use metamath_knife::{database::DbOptions, Database};
 
-use crate::proof::ProofWorksheet;
+use crate::proof::{ProofFormat, ProofWorksheet};
 
 pub(crate) fn mkdb(text: &[u8]) -> Database {
     let options = DbOptions {
@@ -65,6 +65,28 @@ const TEST_DB: &[u8] = b"
     $}
 ";
 
+/// A database with spare `wff` variables, to represent work variables
+const TEST_DB_WORK_VARS: &[u8] = b"
+    $c |- wff ( ) -> $.
+    $( $j syntax 'wff'; syntax '|-' as 'wff'; $)
+    $v ph ps ch th $.
+    wph $f wff ph $.
+    wps $f wff ps $.
+    wch $f wff ch $.
+    wth $f wff th $.
+    wi $a wff ( ph -> ps ) $.
+    ${
+        min $e |- ph $.
+        maj $e |- ( ph -> ps ) $.
+        ax-mp $a |- ps $.
+    $}
+    ax-1 $a |- ( ph -> ( ps -> ph ) ) $.
+    ${
+        a1i.1 $e |- ph $.
+        a1i $p |- ( ps -> ph ) $= ? $.
+    $}
+";
+
 const TEST_PROOF: &str = "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
 
 * Inference introducing an antecedent.  (Contributed by NM, 29-Dec-1992.)
@@ -384,3 +406,451 @@ fn worksheet_insert_newline_at_step_start() {
     );
     assert_eq!(worksheet.diagnostics(), vec![]);
 }
+
+#[test]
+fn step_can_apply() {
+    let db = &mkdb(TEST_DB);
+    let worksheet = ProofWorksheet::from_string(TEST_PROOF.to_string(), db).unwrap();
+    let step = &worksheet.steps[1].step;
+    assert!(step.can_apply(db.statement(b"ax-1").unwrap(), db));
+    // No hypotheses are listed yet, so any hypothesis count is accepted
+    assert!(step.can_apply(db.statement(b"ax-mp").unwrap(), db));
+    assert!(!step.can_apply(db.statement(b"wi").unwrap(), db));
+    let qed = &worksheet.steps[2].step;
+    assert!(qed.can_apply(db.statement(b"ax-mp").unwrap(), db));
+    assert!(!qed.can_apply(db.statement(b"ax-1").unwrap(), db));
+}
+
+#[test]
+fn worksheet_unify() {
+    let db = &mkdb(TEST_DB);
+    let worksheet = ProofWorksheet::from_string(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1
+2::ax-1        |- ( ph -> ( ps -> ph ) )
+qed:?,?:ax-mp  |- ( ps -> ph )
+"
+        .to_string(),
+        db,
+    )
+    .unwrap();
+    let edits = worksheet.unify();
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: LspRange {
+                    start: Position {
+                        line: 2,
+                        character: 9
+                    },
+                    end: Position {
+                        line: 2,
+                        character: 9
+                    },
+                },
+                new_text: " |- ph".to_string(),
+            },
+            TextEdit {
+                range: LspRange {
+                    start: Position {
+                        line: 4,
+                        character: 4
+                    },
+                    end: Position {
+                        line: 4,
+                        character: 7
+                    },
+                },
+                new_text: "1,2".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn worksheet_work_variables() {
+    let db = &mkdb(TEST_DB_WORK_VARS);
+    let worksheet = ProofWorksheet::from_string(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1      |- ph
+2::ax-1        |- ( ph -> &W1 )
+qed:1,2:ax-mp  |- ( ps -> ph )
+"
+        .to_string(),
+        db,
+    )
+    .unwrap();
+    assert_eq!(
+        worksheet.formula_string(worksheet.steps[1].step.formula().unwrap()),
+        "|- ( ph -> &W1 )"
+    );
+    let edits = worksheet.unify();
+    assert_eq!(
+        edits,
+        vec![TextEdit {
+            range: LspRange {
+                start: Position {
+                    line: 3,
+                    character: 15
+                },
+                end: Position {
+                    line: 3,
+                    character: 31
+                },
+            },
+            new_text: "|- ( ph -> ( ps -> ph ) )".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn worksheet_work_variables_exclude_written_variables() {
+    let db = &mkdb(TEST_DB_WORK_VARS);
+    // `th` is the last variable declared, it would be the first one used for a work variable
+    let worksheet = ProofWorksheet::from_string(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+1::            |- ( th -> &W1 )
+"
+        .to_string(),
+        db,
+    )
+    .unwrap();
+    assert_eq!(
+        worksheet.formula_string(worksheet.steps[0].step.formula().unwrap()),
+        "|- ( th -> &W1 )"
+    );
+}
+
+#[test]
+fn worksheet_work_variables_reallocated() {
+    let db = &mkdb(TEST_DB_WORK_VARS);
+    let mut worksheet = ProofWorksheet::from_string(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+1::            |- &W1
+"
+        .to_string(),
+        db,
+    )
+    .unwrap();
+    // Now write the variable which was representing `&W1`
+    worksheet.apply_change(&TextDocumentContentChangeEvent {
+        range: Some(LspRange {
+            start: Position {
+                line: 3,
+                character: 0,
+            },
+            end: Position {
+                line: 3,
+                character: 0,
+            },
+        }),
+        range_length: None,
+        text: "2::            |- th\n".to_string(),
+    });
+    assert_eq!(
+        worksheet.formula_string(worksheet.steps[0].step.formula().unwrap()),
+        "|- &W1"
+    );
+    assert_eq!(
+        worksheet.formula_string(worksheet.steps[1].step.formula().unwrap()),
+        "|- th"
+    );
+}
+
+#[test]
+fn worksheet_work_variables_ignore_comments() {
+    let db = &mkdb(TEST_DB_WORK_VARS);
+    // `th` is only written in a comment, so it may still represent a work variable
+    let worksheet = ProofWorksheet::from_string(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+* Here th is only mentioned
+1::            |- &W1
+"
+        .to_string(),
+        db,
+    )
+    .unwrap();
+    let th = db.name_result().lookup_symbol(b"th").unwrap().atom;
+    assert_eq!(worksheet.work_vars.name(th), Some("&W1"));
+}
+
+#[test]
+fn worksheet_work_variables_no_longer_excluded() {
+    let db = &mkdb(TEST_DB_WORK_VARS);
+    let mut worksheet = ProofWorksheet::from_string(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+1::            |- th
+2::            |- &W1
+"
+        .to_string(),
+        db,
+    )
+    .unwrap();
+    let th = db.name_result().lookup_symbol(b"th").unwrap().atom;
+    assert_eq!(worksheet.work_vars.name(th), None);
+    // Once `th` is no longer written, it may represent a new work variable
+    worksheet.apply_change(&TextDocumentContentChangeEvent {
+        range: Some(LspRange {
+            start: Position {
+                line: 2,
+                character: 18,
+            },
+            end: Position {
+                line: 2,
+                character: 20,
+            },
+        }),
+        range_length: None,
+        text: "ph".to_string(),
+    });
+    worksheet.apply_change(&TextDocumentContentChangeEvent {
+        range: Some(LspRange {
+            start: Position {
+                line: 4,
+                character: 0,
+            },
+            end: Position {
+                line: 4,
+                character: 0,
+            },
+        }),
+        range_length: None,
+        text: "3::            |- &W2\n".to_string(),
+    });
+    assert_eq!(worksheet.work_vars.name(th), Some("&W2"));
+}
+
+#[test]
+fn worksheet_byte_to_lsp_position() {
+    let db = &mkdb(TEST_DB);
+    let worksheet = ProofWorksheet::from_string(TEST_PROOF.to_string(), db).unwrap();
+    // On the continuation line of step 2, the column starts after the newline character
+    let byte_idx = TEST_PROOF.find("    -> ( ps").unwrap() + 4;
+    let position = Position {
+        line: 6,
+        character: 4,
+    };
+    assert_eq!(worksheet.byte_to_lsp_position(byte_idx), position);
+    assert_eq!(worksheet.lsp_position_to_byte(position), byte_idx);
+    // On the first line of a step
+    let byte_idx = TEST_PROOF.find("qed:").unwrap();
+    let position = Position {
+        line: 7,
+        character: 0,
+    };
+    assert_eq!(worksheet.byte_to_lsp_position(byte_idx), position);
+}
+
+#[test]
+fn worksheet_generate_proof() {
+    let db = &mkdb(TEST_DB);
+    let worksheet = ProofWorksheet::from_string(TEST_PROOF.to_string(), db).unwrap();
+    assert_eq!(
+        worksheet.generate_proof(ProofFormat::Compressed).unwrap(),
+        "$=    ( wi ax-1 ax-mp ) ABADCABEF $."
+    );
+    assert_eq!(
+        worksheet.generate_proof(ProofFormat::Normal).unwrap(),
+        "$=    wph wps wph wi a1i.1 wph wps ax-1 ax-mp $."
+    );
+    assert_eq!(
+        worksheet.generate_proof(ProofFormat::Explicit).unwrap(),
+        "$=    wph=wph wph=wps wps=wph wps=wi min=a1i.1 wph=wph wps=wps maj=ax-1 ax-mp\n      $."
+    );
+    assert_eq!(
+        worksheet.proof_edit(ProofFormat::Normal).unwrap(),
+        TextEdit {
+            range: LspRange {
+                start: Position {
+                    line: 9,
+                    character: 0
+                },
+                end: Position {
+                    line: 9,
+                    character: 36
+                },
+            },
+            new_text: "$=    wph wps wph wi a1i.1 wph wps ax-1 ax-mp $.".to_string(),
+        }
+    );
+}
+
+#[test]
+fn worksheet_new_theorem_proof() {
+    let db = &mkdb(TEST_DB);
+    let worksheet = ProofWorksheet::from_string(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i2  LOC_AFTER=ax-1
+
+* Inference introducing an antecedent.
+
+h1::a1i2.1     |- ph
+2::ax-1        |- ( ph -> ( ps -> ph ) )
+qed:1,2:ax-mp  |- ( ps -> ph )
+"
+        .to_string(),
+        db,
+    )
+    .unwrap();
+    assert!(worksheet.sadd.is_none());
+    assert_eq!(worksheet.theorem_label, "a1i2");
+    assert_eq!(
+        worksheet.comment(),
+        Some("Inference introducing an antecedent.")
+    );
+    assert_eq!(
+        worksheet
+            .generate_proof_tokens(ProofFormat::Compressed, 6, 6)
+            .unwrap(),
+        "( wi ax-1 ax-mp ) ABADCABEF $."
+    );
+}
+
+#[test]
+fn worksheet_rename_step() {
+    let db = &mkdb(TEST_DB);
+    let worksheet = ProofWorksheet::from_string(TEST_PROOF.to_string(), db).unwrap();
+    let edit = |line, start, end, new_text: &str| TextEdit {
+        range: LspRange {
+            start: Position {
+                line,
+                character: start,
+            },
+            end: Position {
+                line,
+                character: end,
+            },
+        },
+        new_text: new_text.to_string(),
+    };
+    let (name, range) = worksheet
+        .step_name_at(Position {
+            line: 7,
+            character: 6,
+        })
+        .unwrap();
+    assert_eq!(name, "2");
+    assert_eq!(range, edit(7, 6, 7, "").range);
+    assert_eq!(
+        worksheet.rename_step("2", "5").unwrap(),
+        vec![edit(5, 0, 1, "5"), edit(7, 6, 7, "5")]
+    );
+    assert!(worksheet.rename_step("2", "1").is_err());
+    assert!(worksheet.rename_step("2", "a:b").is_err());
+    assert_eq!(
+        worksheet.renumber(10),
+        vec![
+            edit(4, 1, 2, "10"),
+            edit(5, 0, 1, "20"),
+            edit(7, 4, 5, "10"),
+            edit(7, 6, 7, "20"),
+        ]
+    );
+}
+
+#[test]
+fn worksheet_step_unification() {
+    let db = &mkdb(TEST_DB);
+    let worksheet = ProofWorksheet::from_string(TEST_PROOF.to_string(), db).unwrap();
+    assert!(worksheet.step_unification(0).is_none());
+    let unification = worksheet.step_unification(2).unwrap();
+    assert_eq!(
+        unification.substitutions,
+        vec![
+            ("ph".to_string(), Some("ph".to_string())),
+            ("ps".to_string(), Some("( ps -> ph )".to_string())),
+        ]
+    );
+    assert_eq!(
+        unification.expected_hyps,
+        vec!["|- ph", "|- ( ph -> ( ps -> ph ) )"]
+    );
+    assert!(unification.error.is_none());
+
+    // Swapped hypotheses: the second one does not match
+    let worksheet =
+        ProofWorksheet::from_string(TEST_PROOF.replace("qed:1,2:", "qed:2,1:"), db).unwrap();
+    let unification = worksheet.step_unification(2).unwrap();
+    assert_eq!(unification.error.unwrap().1, Some(1));
+}
+
+#[test]
+fn worksheet_header_label() {
+    let db = &mkdb(TEST_DB);
+    let worksheet = ProofWorksheet::from_string(TEST_PROOF.to_string(), db).unwrap();
+    let label_at = |line, character| worksheet.header_label_at(Position { line, character });
+    assert_eq!(label_at(0, 30), Some("a1i"));
+    assert_eq!(label_at(0, 32), Some("a1i"));
+    assert_eq!(label_at(0, 10), None);
+    assert_eq!(label_at(0, 45), None);
+    assert_eq!(label_at(2, 30), None);
+    // Labels may also be followed by tabs, or by the carriage return of a Windows line ending
+    let text = TEST_PROOF.replacen("a1i  LOC_AFTER=?\n", "a1i\tLOC_AFTER=ax-1\r\n", 1);
+    let worksheet = ProofWorksheet::from_string(text, db).unwrap();
+    let label_at = |line, character| worksheet.header_label_at(Position { line, character });
+    assert_eq!(label_at(0, 32), Some("a1i"));
+    assert_eq!(label_at(0, 47), Some("ax-1"));
+    assert_eq!(label_at(0, 48), None);
+}
+
+#[test]
+fn worksheet_quick_fixes() {
+    let db = &mkdb(TEST_DB);
+    let whole = LspRange {
+        start: Position {
+            line: 0,
+            character: 0,
+        },
+        end: Position {
+            line: 12,
+            character: 0,
+        },
+    };
+    let fixes = |text: String| -> Vec<(String, String)> {
+        let worksheet = ProofWorksheet::from_string(text, db).unwrap();
+        worksheet
+            .quick_fixes(whole)
+            .into_iter()
+            .map(|fix| (fix.title, fix.edit.new_text))
+            .collect()
+    };
+    assert!(fixes(TEST_PROOF.to_string()).is_empty());
+    assert_eq!(
+        fixes(TEST_PROOF.replace("qed:1,2:", "qed:1:")),
+        vec![(
+            "Add placeholders for the missing hypotheses".to_string(),
+            "1,?".to_string()
+        )]
+    );
+    assert_eq!(
+        fixes(TEST_PROOF.replace("2::ax-1", "2::ax-2")),
+        vec![
+            ("Replace with ax-1".to_string(), "ax-1".to_string()),
+            ("Replace with ax-mp".to_string(), "ax-mp".to_string()),
+        ]
+    );
+    assert_eq!(
+        fixes(TEST_PROOF.replace("qed:1,2:", "qed:1,3:")),
+        vec![("Create step 3".to_string(), "3::?\n".to_string())]
+    );
+    // The range may end after the last step
+    let worksheet =
+        ProofWorksheet::from_string(TEST_PROOF.replace("qed:1,2:", "qed:1:"), db).unwrap();
+    let range = LspRange {
+        start: Position {
+            line: 0,
+            character: 0,
+        },
+        end: Position {
+            line: 1000,
+            character: 0,
+        },
+    };
+    assert_eq!(worksheet.quick_fixes(range).len(), 1);
+}
@@ -1,56 +1,205 @@
 //! Provides references to a given statement
 
+use crate::definition::find_definition;
 use crate::definition::find_statement;
-use crate::definition::stmt_location;
+use crate::definition::syntax_axiom;
+use crate::definition::SourceLocations;
+use crate::proof::ProofWorksheet;
 use crate::server::word_at;
+use crate::server::Cancellation;
 use crate::util::FileRef;
 use crate::vfs::Vfs;
 use crate::ServerError;
 use lsp_types::*;
+use memchr::memmem;
+use metamath_knife::comment_parser::CommentItem;
+use metamath_knife::nameck::Atom;
+use metamath_knife::nameck::Nameset;
 use metamath_knife::statement::StatementRef;
 use metamath_knife::statement::StatementType;
+use metamath_knife::statement::TokenPtr;
 use metamath_knife::Database;
+use metamath_knife::Span;
 use std::ops::Bound;
 
+/// The span of the label of a statement, where it is declared
+pub(crate) fn label_span(stmt: &StatementRef<'_>) -> Option<Span> {
+    let buf = &stmt.segment().segment.buffer;
+    let span = stmt.span();
+    let start = span.start as usize + memmem::find(span.as_ref(buf), stmt.label())?;
+    Some(Span::new(start, start + stmt.label().len()))
+}
+
+/// The labels used in the proof of a statement, with their spans.
+/// These are all the tokens of a normal proof, or the label list of a compressed proof.
+pub(crate) fn proof_labels<'a>(stmt: &StatementRef<'a>) -> Vec<(Span, TokenPtr<'a>)> {
+    if stmt.statement_type() != StatementType::Provable {
+        return vec![];
+    }
+    let len = stmt.proof_len();
+    // Compressed proofs start with the list of labels used, between parentheses
+    let compressed = len > 0 && stmt.proof_slice_at(0) == b"(";
+    let start = if compressed { 1 } else { 0 };
+    let mut labels = vec![];
+    for i in start..len {
+        let token = stmt.proof_slice_at(i);
+        if compressed && token == b")" {
+            break;
+        }
+        labels.push((stmt.proof_span(i), token));
+    }
+    labels
+}
+
+/// The spans of the uses of the given label in the proof of a statement.
+/// Both normal and compressed proofs are handled.
+pub(crate) fn proof_uses(stmt: &StatementRef<'_>, label: &[u8]) -> Vec<Span> {
+    proof_labels(stmt)
+        .into_iter()
+        .filter_map(|(span, token)| (token == label).then(|| span))
+        .collect()
+}
+
+/// The spans of the occurrences of the given math symbol in the math string of a statement.
+/// Symbols are compared through their atoms in the name set.
+pub(crate) fn math_uses(stmt: &StatementRef<'_>, symbol: Atom, nset: &Nameset) -> Vec<Span> {
+    (0..stmt.math_len())
+        .filter(|&ix| {
+            nset.lookup_symbol(stmt.math_at(ix).slice)
+                .map_or(false, |lookup| lookup.atom == symbol)
+        })
+        .map(|ix| stmt.math_span(ix))
+        .collect()
+}
+
+/// The spans of the `~ label` references to the given label in a comment
+pub(crate) fn comment_uses(stmt: &StatementRef<'_>, label: &[u8]) -> Vec<Span> {
+    if stmt.statement_type() != StatementType::Comment {
+        return vec![];
+    }
+    let buf = &stmt.segment().segment.buffer;
+    // Only parse the comments which may contain the label
+    if memmem::find(stmt.span().as_ref(buf), label).is_none() {
+        return vec![];
+    }
+    stmt.comment_parser()
+        .filter_map(|item| match item {
+            CommentItem::Label(_, span) if span.as_ref(buf) == label => Some(span),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The ranges of the steps of a proof worksheet applying the given label
+pub(crate) fn worksheet_uses(worksheet: &ProofWorksheet, label: &[u8]) -> Vec<Range> {
+    (0..worksheet.steps.len())
+        .filter(|&step_idx| worksheet.step_label(step_idx) == label)
+        .map(|step_idx| worksheet.span_range(step_idx, worksheet.steps[step_idx].step.label_span()))
+        .collect()
+}
+
+/// The location of the label of a statement
+fn label_location(stmt: StatementRef<'_>, sources: &mut SourceLocations<'_>) -> Option<Location> {
+    sources.location(stmt, label_span(&stmt)?)
+}
+
+/// Lists the references to a math symbol: its syntax axiom, its definition,
+/// and all the statements using it in their math string.
+fn symbol_references(
+    symbol: Atom,
+    include_declaration: bool,
+    vfs: &Vfs,
+    db: &Database,
+    cancel: &Cancellation,
+) -> Result<Vec<Location>, ServerError> {
+    let nset = db.name_result();
+    let mut sources = SourceLocations::new(vfs, db);
+    let mut locations = vec![];
+    if let Some(syntax_label) = syntax_axiom(symbol, db) {
+        let syntax_stmt = db.statement(nset.atom_name(syntax_label));
+        for stmt in [syntax_stmt, find_definition(syntax_label, db)]
+            .into_iter()
+            .flatten()
+        {
+            locations.extend(label_location(stmt, &mut sources));
+        }
+    }
+    for stmt in db.statements() {
+        cancel.check()?;
+        // The `$c` and `$v` statements are where symbols are declared
+        if !include_declaration
+            && matches!(
+                stmt.statement_type(),
+                StatementType::Constant | StatementType::Variable
+            )
+        {
+            continue;
+        }
+        for span in math_uses(&stmt, symbol, nset) {
+            locations.extend(sources.location(stmt, span));
+        }
+    }
+    Ok(locations)
+}
+
+/// Lists the references to the label of a statement: its uses in proofs,
+/// in `~ label` comment markup, and in the proof worksheets loaded.
+pub(crate) fn label_references(
+    stmt: StatementRef<'_>,
+    include_declaration: bool,
+    vfs: &Vfs,
+    db: &Database,
+    cancel: &Cancellation,
+) -> Result<Vec<Location>, ServerError> {
+    let label = stmt.label();
+    let mut sources = SourceLocations::new(vfs, db);
+    let mut locations = vec![];
+    if include_declaration {
+        locations.extend(label_location(stmt, &mut sources));
+    }
+    // Labels can only be used in the proofs of subsequent statements
+    for use_stmt in db.statements_range_address((Bound::Excluded(stmt.address()), Bound::Unbounded))
+    {
+        cancel.check()?;
+        for span in proof_uses(&use_stmt, label) {
+            locations.extend(sources.location(use_stmt, span));
+        }
+    }
+    // Comments may refer to any label
+    for comment_stmt in db.statements() {
+        cancel.check()?;
+        for span in comment_uses(&comment_stmt, label) {
+            locations.extend(sources.location(comment_stmt, span));
+        }
+    }
+    for (path, worksheet) in vfs.worksheets() {
+        for range in worksheet_uses(&worksheet, label) {
+            locations.push(Location {
+                uri: path.url().clone(),
+                range,
+            });
+        }
+    }
+    Ok(locations)
+}
+
 pub(crate) fn references(
     path: FileRef,
     pos: Position,
+    include_declaration: bool,
     vfs: &Vfs,
     db: Database,
+    cancel: &Cancellation,
 ) -> Result<Vec<Location>, ServerError> {
     let text = vfs.source(path, &db)?;
     let (word, _range) = word_at(pos, text);
-    let label = word.as_bytes();
-    if let Some(stmt) = find_statement(label, &db) {
-        let mut locations = vec![];
-        for stmt in db.statements_range_address((Bound::Excluded(stmt.address()), Bound::Unbounded))
-        {
-            if is_direct_use(&stmt, label) {
-                if let Some(location) = stmt_location(stmt, vfs, &db) {
-                    locations.push(location);
-                }
-            }
+    if db.statement(word.as_bytes()).is_none() {
+        if let Some(symbol) = db.name_result().lookup_symbol(word.as_bytes()) {
+            return symbol_references(symbol.atom, include_declaration, vfs, &db, cancel);
         }
-        Ok(locations)
-    } else {
-        Ok(Vec::new())
     }
-}
-
-fn is_direct_use(stmt: &StatementRef<'_>, label: &[u8]) -> bool {
-    stmt.statement_type() == StatementType::Provable && {
-        let len = stmt.proof_len();
-        if len == 0 || stmt.proof_slice_at(0) != b"(" {
-            return false;
-        }
-        for i in 1..len {
-            let ref_stmt = stmt.proof_slice_at(i);
-            if ref_stmt == b")" {
-                break;
-            } else if ref_stmt == label {
-                return true;
-            }
-        }
-        false
+    match find_statement(word.as_bytes(), &db) {
+        Some(stmt) => label_references(stmt, include_declaration, vfs, &db, cancel),
+        None => Ok(Vec::new()),
     }
 }
@@ -0,0 +1,81 @@
+use crate::references::references;
+use crate::server::Cancellation;
+use crate::test_fixtures::{open_db, open_worksheet, TEST_DB};
+use lsp_types::*;
+
+#[test]
+fn references_cancelled() {
+    let (path, vfs, db) = open_db("references_cancelled.mm", TEST_DB);
+    let cancel = Cancellation::default();
+    let pos = Position::new(10, 1);
+    assert!(references(path.clone(), pos, true, &vfs, db.clone(), &cancel).is_ok());
+    cancel.cancel();
+    assert!(references(path, pos, true, &vfs, db, &cancel).is_err());
+}
+
+#[test]
+fn references_to_a_label() {
+    let text = format!(
+        "{}$( See ~ ax-1 . $)\nth1 $p |- ( ph -> ( ph -> ph ) ) $= wph wph ax-1 $.\n",
+        TEST_DB
+    );
+    let (path, vfs, db) = open_db("references_label.mm", &text);
+    let worksheet = open_worksheet(
+        "references_label.mmp",
+        "$( <MM> <PROOF_ASST> THEOREM=th2  LOC_AFTER=?
+
+qed::ax-1      |- ( ps -> ( ph -> ps ) )
+",
+        &vfs,
+        &db,
+    );
+    let cancel = Cancellation::default();
+    let pos = Position::new(10, 1);
+    let locations = references(path.clone(), pos, true, &vfs, db.clone(), &cancel).unwrap();
+    let location = |uri: &Url, line, start, end| Location {
+        uri: uri.clone(),
+        range: Range::new(Position::new(line, start), Position::new(line, end)),
+    };
+    assert_eq!(
+        locations,
+        vec![
+            location(path.url(), 10, 0, 4),
+            location(path.url(), 16, 44, 48),
+            location(path.url(), 15, 9, 13),
+            location(worksheet.url(), 2, 5, 9),
+        ]
+    );
+    let locations = references(path, pos, false, &vfs, db, &cancel).unwrap();
+    assert_eq!(locations.len(), 3);
+}
+
+#[test]
+fn references_in_a_missing_file() {
+    let (path, vfs, db) = open_db("references_missing.mm", TEST_DB);
+    // The database file is only open in the editor, its locations cannot be built
+    std::fs::remove_file(path.path()).unwrap();
+    let cancel = Cancellation::default();
+    let locations = references(path, Position::new(10, 1), true, &vfs, db, &cancel).unwrap();
+    assert!(locations.is_empty());
+}
+
+#[test]
+fn references_to_a_symbol() {
+    let (path, vfs, db) = open_db("references_symbol.mm", TEST_DB);
+    let cancel = Cancellation::default();
+    // The `ps` variable, as declared in the `$v` statement
+    let pos = Position::new(1, 7);
+    let declaration = Range::new(Position::new(1, 6), Position::new(1, 8));
+    let locations = references(path.clone(), pos, true, &vfs, db.clone(), &cancel).unwrap();
+    assert!(locations
+        .iter()
+        .any(|location| location.range == declaration));
+    // The use in the math string of `wi`
+    let uses = Range::new(Position::new(4, 18), Position::new(4, 20));
+    assert!(locations.iter().any(|location| location.range == uses));
+    let without_declaration = references(path, pos, false, &vfs, db, &cancel).unwrap();
+    assert_eq!(without_declaration.len(), locations.len() - 1);
+    assert!(!without_declaration
+        .iter()
+        .any(|location| location.range == declaration));
+}
@@ -0,0 +1,106 @@
+//! Handles rename requests
+//! Step names can be renamed within a proof worksheet, and statement labels across the database.
+
+use crate::references::label_references;
+use crate::server::is_label_char;
+use crate::server::word_at;
+use crate::server::Cancellation;
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::Database;
+use std::collections::HashMap;
+
+/// Checks that a statement can be renamed to the given label:
+/// it shall be a valid label, and not collide with any existing label or math symbol.
+fn check_new_label(new_label: &str, db: &Database) -> Result<(), ServerError> {
+    if new_label.is_empty() || !new_label.chars().all(is_label_char) {
+        return Err(format!("Invalid label {}", new_label).into());
+    }
+    let nset = db.name_result();
+    if nset.lookup_label(new_label.as_bytes()).is_some() {
+        return Err(format!("Label {} already exists", new_label).into());
+    }
+    if nset.lookup_symbol(new_label.as_bytes()).is_some() {
+        return Err(format!("{} is already a math symbol", new_label).into());
+    }
+    Ok(())
+}
+
+/// Builds the edits renaming a statement label everywhere it is used
+fn rename_label(
+    old_label: &str,
+    new_label: &str,
+    vfs: &Vfs,
+    db: &Database,
+    cancel: &Cancellation,
+) -> Result<Option<WorkspaceEdit>, ServerError> {
+    let stmt = match db.statement(old_label.as_bytes()) {
+        Some(stmt) => stmt,
+        None => return Ok(None),
+    };
+    check_new_label(new_label, db)?;
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for Location { uri, range } in label_references(stmt, true, vfs, db, cancel)? {
+        changes.entry(uri).or_default().push(TextEdit {
+            range,
+            new_text: new_label.to_string(),
+        });
+    }
+    Ok(Some(WorkspaceEdit {
+        changes: Some(changes),
+        ..WorkspaceEdit::default()
+    }))
+}
+
+pub(crate) fn prepare_rename(
+    path: FileRef,
+    pos: Position,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Option<PrepareRenameResponse>, ServerError> {
+    match vfs.source(path, &db)? {
+        FileContents::MMPFile(worksheet) => Ok(worksheet
+            .step_name_at(pos)
+            .map(|(_, range)| PrepareRenameResponse::Range(range))),
+        source @ FileContents::MMFile(_) => {
+            // Only statement labels can be renamed, not math symbols
+            let (word, range) = word_at(pos, source);
+            Ok(db
+                .statement(word.as_bytes())
+                .map(|_| PrepareRenameResponse::Range(range)))
+        }
+    }
+}
+
+pub(crate) fn rename(
+    path: FileRef,
+    pos: Position,
+    new_name: String,
+    vfs: &Vfs,
+    db: Database,
+    cancel: &Cancellation,
+) -> Result<Option<WorkspaceEdit>, ServerError> {
+    let uri = path.url().clone();
+    match vfs.source(path, &db)? {
+        FileContents::MMPFile(worksheet) => {
+            let old_name = match worksheet.step_name_at(pos) {
+                Some((old_name, _)) => old_name,
+                None => return Ok(None),
+            };
+            let edits = worksheet.rename_step(old_name, &new_name)?;
+            let mut changes = HashMap::new();
+            changes.insert(uri, edits);
+            Ok(Some(WorkspaceEdit {
+                changes: Some(changes),
+                ..WorkspaceEdit::default()
+            }))
+        }
+        source @ FileContents::MMFile(_) => {
+            let (word, _) = word_at(pos, source);
+            rename_label(&word, &new_name, vfs, &db, cancel)
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::rename::rename;
+use crate::server::Cancellation;
+use crate::test_fixtures::{open_db, open_worksheet, TEST_DB};
+use lsp_types::*;
+
+#[test]
+fn rename_label_across_files() {
+    let text = format!(
+        "{}$( See ~ ax-1 . $)\nth1 $p |- ( ph -> ( ph -> ph ) ) $= wph wph ax-1 $.\n",
+        TEST_DB
+    );
+    let (path, vfs, db) = open_db("rename_label.mm", &text);
+    let worksheet = open_worksheet(
+        "rename_label.mmp",
+        "$( <MM> <PROOF_ASST> THEOREM=th2  LOC_AFTER=?
+
+qed::ax-1      |- ( ps -> ( ph -> ps ) )
+",
+        &vfs,
+        &db,
+    );
+    let cancel = Cancellation::default();
+    let pos = Position::new(10, 1);
+    let edit = rename(
+        path.clone(),
+        pos,
+        "ax-k".to_string(),
+        &vfs,
+        db.clone(),
+        &cancel,
+    )
+    .unwrap()
+    .unwrap();
+    let changes = edit.changes.unwrap();
+    let mut ranges: Vec<_> = changes[path.url()].iter().map(|edit| edit.range).collect();
+    ranges.sort_by_key(|range| range.start);
+    assert_eq!(
+        ranges,
+        vec![
+            Range::new(Position::new(10, 0), Position::new(10, 4)),
+            Range::new(Position::new(15, 9), Position::new(15, 13)),
+            Range::new(Position::new(16, 44), Position::new(16, 48)),
+        ]
+    );
+    assert_eq!(changes[worksheet.url()].len(), 1);
+    assert!(changes
+        .values()
+        .flatten()
+        .all(|edit| edit.new_text == "ax-k"));
+    // The new label shall not collide with an existing one
+    for new_label in ["ax-mp", "ph", "ax 2"] {
+        let result = rename(
+            path.clone(),
+            pos,
+            new_label.to_string(),
+            &vfs,
+            db.clone(),
+            &cancel,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! Handles step renumbering requests
+
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::Database;
+use std::collections::HashMap;
+
+pub(crate) fn renumber(
+    path: FileRef,
+    increment: usize,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Option<WorkspaceEdit>, ServerError> {
+    // All the steps would get the same number
+    if increment == 0 {
+        return Err("The renumbering increment shall be at least 1".into());
+    }
+    let uri = path.url().clone();
+    if let FileContents::MMPFile(worksheet) = vfs.source(path, &db)? {
+        let edits = worksheet.renumber(increment);
+        if edits.is_empty() {
+            return Ok(None);
+        }
+        let mut changes = HashMap::new();
+        changes.insert(uri, edits);
+        Ok(Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..WorkspaceEdit::default()
+        }))
+    } else {
+        Ok(None)
+    }
+}
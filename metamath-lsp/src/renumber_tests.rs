@@ -0,0 +1,30 @@
+use crate::renumber::renumber;
+use crate::test_fixtures::{open_db, open_worksheet, TEST_DB};
+
+#[test]
+fn renumber_with_zero_increment() {
+    let (_, vfs, db) = open_db("renumber_zero.mm", TEST_DB);
+    let path = open_worksheet(
+        "renumber_zero.mmp",
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1      |- ph
+5::ax-1        |- ( ph -> ( ps -> ph ) )
+qed:1,5:ax-mp  |- ( ps -> ph )
+",
+        &vfs,
+        &db,
+    );
+    assert!(renumber(path.clone(), 0, &vfs, db.clone()).is_err());
+    let edit = renumber(path, 1, &vfs, db).unwrap();
+    assert_eq!(
+        edit.unwrap()
+            .changes
+            .unwrap()
+            .into_values()
+            .next()
+            .unwrap()
+            .len(),
+        2
+    );
+}
@@ -15,6 +15,24 @@ use xi_rope::Interval;
 use xi_rope::Rope;
 use xi_rope::RopeInfo;
 
+/// Converts an LSP character offset within a line, which counts UTF-16 code units,
+/// into a byte index within that line. Offsets past the end of the line are clamped to it.
+pub fn utf16_to_byte_idx(line: &str, character: u32) -> usize {
+    let mut utf16_idx = 0;
+    for (byte_idx, c) in line.char_indices() {
+        if utf16_idx >= character as usize || c == '\n' {
+            return byte_idx;
+        }
+        utf16_idx += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Converts a byte index within a line into an LSP character offset, which counts UTF-16 code units
+pub fn byte_to_utf16_idx(line: &str, byte_idx: usize) -> u32 {
+    line[..byte_idx].encode_utf16().count() as u32
+}
+
 pub trait StringTreeBuilder {
     fn push_string(&mut self, s: &str);
 }
@@ -39,12 +57,13 @@ where
     fn byte_to_lsp_position(&self, byte_idx: usize) -> Position {
         let line_idx = self.offset_to_line(byte_idx);
         let start_line_idx = self.line_to_offset(line_idx);
-        Position::new(line_idx as u32, (byte_idx - start_line_idx) as u32)
+        let before = self.cow_for_range(start_line_idx..byte_idx);
+        Position::new(line_idx as u32, byte_to_utf16_idx(&before, before.len()))
     }
 
     fn lsp_position_to_byte(&self, position: Position) -> usize {
         let start_line_idx = self.line_to_offset(position.line as usize);
-        start_line_idx + position.character as usize
+        start_line_idx + utf16_to_byte_idx(&self.line(position.line), position.character)
     }
 
     fn line(&self, line_idx: u32) -> Cow<str> {
@@ -61,8 +80,6 @@ where
         change: &TextDocumentContentChangeEvent,
     ) -> Result<Delta<I>, Error> {
         let text = change.text.as_str();
-        let text_bytes = text.as_bytes();
-        let text_end_byte_idx = text_bytes.len();
 
         let interval = if let Some(range) = change.range {
             Interval::new(
@@ -70,7 +87,8 @@ where
                 self.lsp_position_to_byte(range.end),
             )
         } else {
-            Interval::new(0, text_end_byte_idx)
+            // The whole document is replaced
+            Interval::new(0, self.char_len())
         };
 
         let mut b = TreeBuilder::new();
@@ -0,0 +1,343 @@
+//! Provides semantic tokens
+//! Math tokens are colored according to their typecode, and labels according to the type
+//! of the statement they refer to. In proof worksheets, step names and hypothesis references
+//! are also highlighted.
+
+use crate::proof::work_variable_typecode;
+use crate::proof::ProofWorksheet;
+use crate::rope_ext::byte_to_utf16_idx;
+use crate::rope_ext::RopeExt;
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::nameck::Nameset;
+use metamath_knife::nameck::SymbolType;
+use metamath_knife::Database;
+use metamath_knife::StatementType;
+use xi_rope::Rope;
+
+/// The semantic token types, in the order of the legend
+#[derive(Clone, Copy)]
+enum TokenType {
+    /// A math constant
+    Constant,
+    /// A variable of a typecode other than `wff`, `setvar` or `class`
+    Variable,
+    /// A `wff` variable
+    Wff,
+    /// A `setvar` variable
+    Setvar,
+    /// A `class` variable
+    Class,
+    /// The label of a theorem
+    Theorem,
+    /// The label of an axiom, or of a definition
+    Axiom,
+    /// The label of a floating or essential hypothesis
+    Hypothesis,
+    /// The name of a proof worksheet step, or a reference to it
+    Step,
+}
+
+/// Modifier for the token where a label or a step name is declared
+const DECLARATION: u32 = 1 << 0;
+/// Modifier for the labels of definitions
+const DEFINITION: u32 = 1 << 1;
+
+/// The legend of the semantic tokens provided, sent with the server capabilities
+pub(crate) fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: vec![
+            SemanticTokenType::OPERATOR,
+            SemanticTokenType::VARIABLE,
+            SemanticTokenType::new("wff"),
+            SemanticTokenType::new("setvar"),
+            SemanticTokenType::CLASS,
+            SemanticTokenType::FUNCTION,
+            SemanticTokenType::INTERFACE,
+            SemanticTokenType::PARAMETER,
+            SemanticTokenType::ENUM_MEMBER,
+        ],
+        token_modifiers: vec![
+            SemanticTokenModifier::DECLARATION,
+            SemanticTokenModifier::DEFINITION,
+        ],
+    }
+}
+
+/// Accumulates semantic tokens, each one being encoded relative to the previous one.
+/// Tokens shall be pushed in the order of the document.
+struct TokenBuilder {
+    /// Only the tokens starting within this range are kept, if any
+    range: Option<Range>,
+    last: Position,
+    tokens: Vec<SemanticToken>,
+}
+
+impl TokenBuilder {
+    fn new(range: Option<Range>) -> Self {
+        Self {
+            range,
+            last: Position::default(),
+            tokens: vec![],
+        }
+    }
+
+    /// Whether the given line is past the requested range
+    fn is_past(&self, line_idx: u32) -> bool {
+        self.range.map_or(false, |range| line_idx > range.end.line)
+    }
+
+    fn push(&mut self, start: Position, length: usize, token_type: TokenType, modifiers: u32) {
+        if let Some(range) = self.range {
+            if start < range.start || start >= range.end {
+                return;
+            }
+        }
+        let delta_line = start.line - self.last.line;
+        let delta_start = if delta_line == 0 {
+            start.character - self.last.character
+        } else {
+            start.character
+        };
+        self.tokens.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: length as u32,
+            token_type: token_type as u32,
+            token_modifiers_bitset: modifiers,
+        });
+        self.last = start;
+    }
+}
+
+/// The token type for variables of the given typecode
+fn typecode_token_type(typecode: &[u8]) -> TokenType {
+    match typecode {
+        b"wff" => TokenType::Wff,
+        b"setvar" => TokenType::Setvar,
+        b"class" => TokenType::Class,
+        _ => TokenType::Variable,
+    }
+}
+
+/// The token type of a math token, or `None` if it is not a known math token
+fn math_token_type(token: &str, nset: &Nameset) -> Option<TokenType> {
+    if let Some(typecode) = work_variable_typecode(token) {
+        return Some(typecode_token_type(typecode));
+    }
+    let symbol = nset.lookup_symbol(token.as_bytes())?;
+    match symbol.stype {
+        SymbolType::Constant => Some(TokenType::Constant),
+        SymbolType::Variable => Some(
+            nset.lookup_float(token.as_bytes())
+                .map_or(TokenType::Variable, |float| {
+                    typecode_token_type(nset.atom_name(float.typecode_atom))
+                }),
+        ),
+    }
+}
+
+/// The token type and modifiers of a label, depending on the type of the statement
+fn label_token_type(stmt_type: StatementType, label: &[u8]) -> Option<(TokenType, u32)> {
+    match stmt_type {
+        StatementType::Provable => Some((TokenType::Theorem, 0)),
+        StatementType::Axiom if label.starts_with(b"df-") => Some((TokenType::Axiom, DEFINITION)),
+        StatementType::Axiom => Some((TokenType::Axiom, 0)),
+        StatementType::Essential | StatementType::Floating => Some((TokenType::Hypothesis, 0)),
+        _ => None,
+    }
+}
+
+/// Pushes the token for a label used in a proof
+fn push_label_use(builder: &mut TokenBuilder, pos: Position, label: &str, db: &Database) {
+    if let Some(stmt) = db.statement(label.as_bytes()) {
+        if let Some((token_type, modifiers)) = label_token_type(stmt.statement_type(), stmt.label())
+        {
+            builder.push(pos, label.len(), token_type, modifiers);
+        }
+    }
+}
+
+/// Where we are within a Metamath database file
+enum MmState {
+    /// Between statements, where labels are declared
+    Top,
+    /// Within a file inclusion
+    Include,
+    /// Within the math string of a statement
+    Math,
+    /// Within a normal proof, or the label list of a compressed proof
+    Proof,
+    /// Within the steps of a compressed proof
+    CompressedSteps,
+}
+
+/// Whether a line starts a statement, so that lexing can start there without any prior state:
+/// its first token is a block delimiter, a `$c`, `$v` or `$d` keyword, or a label followed by
+/// a `$a`, `$p`, `$e` or `$f` keyword. Lines within comments are seldom written this way.
+fn starts_statement(line: &str) -> bool {
+    let mut tokens = line.split_ascii_whitespace();
+    match tokens.next() {
+        Some("${" | "$}" | "$[" | "$c" | "$v" | "$d") => true,
+        Some(token) if !token.starts_with('$') => {
+            matches!(tokens.next(), Some("$a" | "$p" | "$e" | "$f"))
+        }
+        _ => false,
+    }
+}
+
+/// The line from which to lex a Metamath database file to find the tokens of the given range:
+/// the last line starting a statement, up to the start of the range.
+fn first_lexed_line(text: &Rope, range: Range) -> u32 {
+    (0..=range.start.line)
+        .rev()
+        .find(|&line_idx| starts_statement(&text.line(line_idx)))
+        .unwrap_or(0)
+}
+
+/// Lexes a Metamath database file, starting at the given line, and pushes the semantic tokens found.
+/// This works on the text itself rather than on the database, which may not have
+/// been parsed again yet after an edit.
+fn mm_tokens(text: &str, first_line_idx: u32, builder: &mut TokenBuilder, db: &Database) {
+    let nset = db.name_result();
+    let mut state = MmState::Top;
+    // Comments may also appear within statements, so they do not change the state
+    let mut in_comment = false;
+    let mut pending_label: Option<(Position, &str)> = None;
+    for (line_idx, line) in (first_line_idx..).zip(text.lines()) {
+        if builder.is_past(line_idx) {
+            break;
+        }
+        for token in line.split_ascii_whitespace() {
+            let byte_idx = token.as_ptr() as usize - line.as_ptr() as usize;
+            let pos = Position::new(line_idx, byte_to_utf16_idx(line, byte_idx));
+            if in_comment {
+                in_comment = token != "$)";
+                continue;
+            }
+            match token {
+                "$(" => in_comment = true,
+                "$[" => state = MmState::Include,
+                "$a" | "$p" | "$e" | "$f" => {
+                    let stmt_type = match token {
+                        "$a" => StatementType::Axiom,
+                        "$p" => StatementType::Provable,
+                        "$e" => StatementType::Essential,
+                        _ => StatementType::Floating,
+                    };
+                    if let Some((label_pos, label)) = pending_label.take() {
+                        if let Some((token_type, modifiers)) =
+                            label_token_type(stmt_type, label.as_bytes())
+                        {
+                            builder.push(
+                                label_pos,
+                                label.len(),
+                                token_type,
+                                modifiers | DECLARATION,
+                            );
+                        }
+                    }
+                    state = MmState::Math;
+                }
+                "$c" | "$v" | "$d" => {
+                    pending_label = None;
+                    state = MmState::Math;
+                }
+                "$=" => state = MmState::Proof,
+                "$." | "$]" | "${" | "$}" => {
+                    pending_label = None;
+                    state = MmState::Top;
+                }
+                _ => match state {
+                    MmState::Top => pending_label = Some((pos, token)),
+                    MmState::Math => {
+                        if let Some(token_type) = math_token_type(token, nset) {
+                            builder.push(pos, token.len(), token_type, 0);
+                        }
+                    }
+                    MmState::Proof => match token {
+                        "(" => {}
+                        ")" => state = MmState::CompressedSteps,
+                        _ => push_label_use(builder, pos, token, db),
+                    },
+                    MmState::Include | MmState::CompressedSteps => {}
+                },
+            }
+        }
+    }
+}
+
+/// Pushes the semantic tokens of the steps of a proof worksheet
+fn mmp_tokens(worksheet: &ProofWorksheet, builder: &mut TokenBuilder, db: &Database) {
+    let nset = db.name_result();
+    for (step_idx, step_info) in worksheet.steps.iter().enumerate() {
+        if builder.is_past(step_info.line_idx as u32) {
+            break;
+        }
+        let step = &step_info.step;
+        let name_span = step.name_span();
+        let name = name_span.as_ref(&step_info.source);
+        if !name.is_empty() {
+            let pos = worksheet.span_range(step_idx, name_span).start;
+            builder.push(pos, name.len(), TokenType::Step, DECLARATION);
+        }
+        for hyp_span in step.hyps() {
+            let hyp = hyp_span.as_ref(&step_info.source);
+            if hyp != "?" {
+                let pos = worksheet.span_range(step_idx, hyp_span).start;
+                builder.push(pos, hyp.len(), TokenType::Step, 0);
+            }
+        }
+        let label_span = step.label_span();
+        let label = label_span.as_ref(&step_info.source);
+        let pos = worksheet.span_range(step_idx, label_span).start;
+        if step.is_hyp() {
+            // Hypotheses of a new theorem are not in the database yet
+            builder.push(pos, label.len(), TokenType::Hypothesis, 0);
+        } else {
+            push_label_use(builder, pos, label, db);
+        }
+        if let Some(formula_span) = step.formula_span() {
+            let formula = formula_span.as_ref(&step_info.source);
+            let offset = formula_span.as_range(step_info.byte_idx).start;
+            for token in formula.split_ascii_whitespace() {
+                if let Some(token_type) = math_token_type(token, nset) {
+                    let byte_idx = offset + token.as_ptr() as usize - formula.as_ptr() as usize;
+                    let pos = worksheet.byte_to_lsp_position(byte_idx);
+                    builder.push(pos, token.len(), token_type, 0);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn semantic_tokens(
+    path: FileRef,
+    range: Option<Range>,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Option<SemanticTokens>, ServerError> {
+    let mut builder = TokenBuilder::new(range);
+    match vfs.source(path, &db)? {
+        FileContents::MMFile(text) => match range {
+            // Only lex the lines of the statements within the range
+            Some(range) => {
+                let first_line_idx = first_lexed_line(&text, range);
+                let source = text.cow_for_range(
+                    text.line_to_offset(first_line_idx as usize)
+                        ..text.line_to_offset(range.end.line as usize + 1),
+                );
+                mm_tokens(&source, first_line_idx, &mut builder, &db)
+            }
+            None => mm_tokens(&text.cow_for_range(..), 0, &mut builder, &db),
+        },
+        FileContents::MMPFile(worksheet) => mmp_tokens(&worksheet, &mut builder, &db),
+    }
+    Ok(Some(SemanticTokens {
+        result_id: None,
+        data: builder.tokens,
+    }))
+}
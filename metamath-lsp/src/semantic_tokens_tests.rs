@@ -0,0 +1,47 @@
+use crate::semantic_tokens::semantic_tokens;
+use crate::test_fixtures::{open_db, TEST_DB};
+use lsp_types::*;
+
+/// The semantic tokens with their absolute positions: line, start character, length and type
+fn absolute_tokens(tokens: Option<SemanticTokens>) -> Vec<(u32, u32, u32, u32)> {
+    let mut last = (0, 0);
+    tokens
+        .unwrap()
+        .data
+        .into_iter()
+        .map(|token| {
+            last = if token.delta_line == 0 {
+                (last.0, last.1 + token.delta_start)
+            } else {
+                (last.0 + token.delta_line, token.delta_start)
+            };
+            (last.0, last.1, token.length, token.token_type)
+        })
+        .collect()
+}
+
+#[test]
+fn semantic_tokens_in_range() {
+    let text = format!(
+        "{}th1 $p |- ( ph\n  -> ( ph -> ph ) ) $= wph wph ax-1 $.\n",
+        TEST_DB
+    );
+    let (path, vfs, db) = open_db("semantic_tokens.mm", &text);
+    let all_tokens =
+        absolute_tokens(semantic_tokens(path.clone(), None, &vfs, db.clone()).unwrap());
+    // The label of `wi`, and its math tokens
+    assert_eq!(all_tokens.iter().filter(|token| token.0 == 4).count(), 7);
+    // The second range starts within the statement of `th1`
+    for (start, end) in [(7, 9), (16, 17), (12, 16)] {
+        let range = Range::new(Position::new(start, 0), Position::new(end, 0));
+        let tokens =
+            absolute_tokens(semantic_tokens(path.clone(), Some(range), &vfs, db.clone()).unwrap());
+        let expected: Vec<_> = all_tokens
+            .iter()
+            .copied()
+            .filter(|token| (start..end).contains(&token.0))
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(tokens, expected);
+    }
+}
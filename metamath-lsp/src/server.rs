@@ -1,20 +1,47 @@
 //! LSP Server Implementation, responsible for dispatching LSP
 //! requests/replies and notifications back to the client.
 
+use crate::code_action::code_actions;
+use crate::code_lens::code_lens;
+use crate::code_lens::Usages;
+use crate::completion::completion;
+use crate::completion::completion_resolve;
 use crate::definition::definition;
 use crate::diag::make_lsp_diagnostic;
+use crate::document_highlight::document_highlight;
+use crate::folding_range::folding_ranges;
+use crate::generate_proof::generate_proof;
 use crate::hover::hover;
 use crate::inlay_hints::inlay_hints;
 use crate::inlay_hints::toggle_hints;
 use crate::outline::outline;
 use crate::references::references;
+use crate::rename::prepare_rename;
+use crate::rename::rename;
+use crate::renumber::renumber;
+use crate::rope_ext::{byte_to_utf16_idx, utf16_to_byte_idx};
+use crate::semantic_tokens::semantic_tokens;
 use crate::show_proof::show_proof;
+use crate::store_proof::store_proof;
+use crate::types::GenerateProofParams;
+use crate::types::RenumberParams;
+use crate::types::ServerOptions;
 use crate::types::ShowProofParams;
+use crate::types::StoreProofParams;
+use crate::types::UnifyParams;
+use crate::typesetting::Typesetting;
+use crate::unify::unify;
+use crate::util::is_same_file;
+use crate::util::FileRef;
 use crate::vfs::FileContents;
 use crate::vfs::Vfs;
+use crate::workspace_symbols::workspace_symbols;
+use crate::MutexExt;
 use crate::Result;
 use crate::ServerError;
 use crossbeam::channel::RecvError;
+use crossbeam::channel::RecvTimeoutError;
+use futures::executor::ThreadPool;
 use lazy_static::lazy_static;
 use log::*;
 use lsp_server::{
@@ -26,7 +53,9 @@ use metamath_knife::{database::DbOptions, Database};
 use serde::ser::Serialize;
 use serde_json::{from_value, to_value};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 enum RequestType {
@@ -35,11 +64,23 @@ enum RequestType {
     Hover(TextDocumentPositionParams),
     Definition(TextDocumentPositionParams),
     DocumentSymbol(DocumentSymbolParams),
+    WorkspaceSymbol(WorkspaceSymbolParams),
     References(ReferenceParams),
     DocumentHighlight(DocumentHighlightParams),
     InlayHint(InlayHintParams),
+    FoldingRange(FoldingRangeParams),
+    CodeLens(CodeLensParams),
+    CodeAction(CodeActionParams),
+    SemanticTokens(SemanticTokensParams),
+    SemanticTokensRange(SemanticTokensRangeParams),
     ShowProof(ShowProofParams),
     ToggleDv,
+    Unify(UnifyParams),
+    GenerateProof(GenerateProofParams),
+    StoreProof(StoreProofParams),
+    PrepareRename(TextDocumentPositionParams),
+    Rename(RenameParams),
+    Renumber(RenumberParams),
 }
 
 fn parse_request(
@@ -53,13 +94,29 @@ fn parse_request(
         "textDocument/documentSymbol" => {
             Some((id, RequestType::DocumentSymbol(from_value(params)?)))
         }
+        "workspace/symbol" => Some((id, RequestType::WorkspaceSymbol(from_value(params)?))),
         "textDocument/references" => Some((id, RequestType::References(from_value(params)?))),
         "textDocument/documentHighlight" => {
             Some((id, RequestType::DocumentHighlight(from_value(params)?)))
         }
         "textDocument/inlayHint" => Some((id, RequestType::InlayHint(from_value(params)?))),
+        "textDocument/foldingRange" => Some((id, RequestType::FoldingRange(from_value(params)?))),
+        "textDocument/codeLens" => Some((id, RequestType::CodeLens(from_value(params)?))),
+        "textDocument/codeAction" => Some((id, RequestType::CodeAction(from_value(params)?))),
+        "textDocument/semanticTokens/full" => {
+            Some((id, RequestType::SemanticTokens(from_value(params)?)))
+        }
+        "textDocument/semanticTokens/range" => {
+            Some((id, RequestType::SemanticTokensRange(from_value(params)?)))
+        }
         "metamath/toggleDv" => Some((id, RequestType::ToggleDv)),
         "metamath/showProof" => Some((id, RequestType::ShowProof(from_value(params)?))),
+        "metamath/unify" => Some((id, RequestType::Unify(from_value(params)?))),
+        "metamath/generateProof" => Some((id, RequestType::GenerateProof(from_value(params)?))),
+        "metamath/storeProof" => Some((id, RequestType::StoreProof(from_value(params)?))),
+        "textDocument/prepareRename" => Some((id, RequestType::PrepareRename(from_value(params)?))),
+        "textDocument/rename" => Some((id, RequestType::Rename(from_value(params)?))),
+        "metamath/renumber" => Some((id, RequestType::Renumber(from_value(params)?))),
         _ => None,
     })
 }
@@ -94,7 +151,7 @@ fn is_token_char(c: char) -> bool {
 }
 
 /// Check if a character can be part of a Metamath label.
-fn is_label_char(c: char) -> bool {
+pub(crate) fn is_label_char(c: char) -> bool {
     c == '.'
         || c == '-'
         || c == '_'
@@ -106,35 +163,69 @@ fn is_label_char(c: char) -> bool {
 /// Attempts to find a word around the given position
 pub fn word_at(pos: Position, source: FileContents) -> (String, Range) {
     let line = source.line(pos.line);
-    let mut start = 0;
-    let mut end = line.len() as u32;
-    for (idx, ch) in line.chars().enumerate() {
-        if !is_token_char(ch) {
-            if idx < pos.character as usize {
-                start = (idx + 1) as u32;
-            } else {
-                end = idx as u32;
-                break;
-            }
-        }
-    }
+    let cursor = utf16_to_byte_idx(&line, pos.character);
+    let start = line[..cursor]
+        .char_indices()
+        .rev()
+        .find(|&(_, ch)| !is_token_char(ch))
+        .map_or(0, |(idx, ch)| idx + ch.len_utf8());
+    let end = line[cursor..]
+        .find(|ch| !is_token_char(ch))
+        .map_or(line.len(), |idx| cursor + idx);
     (
-        line[start as usize..end as usize].to_string(),
-        Range::new(Position::new(pos.line, start), Position::new(pos.line, end)),
+        line[start..end].to_string(),
+        Range::new(
+            Position::new(pos.line, byte_to_utf16_idx(&line, start)),
+            Position::new(pos.line, byte_to_utf16_idx(&line, end)),
+        ),
     )
 }
 
+/// The cancellation flag of a request, set when the client sends a `$/cancelRequest` notification.
+/// Handlers going through the whole database check it regularly, and stop early once it is set.
+#[derive(Clone, Default)]
+pub(crate) struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns an error if the request has been cancelled
+    pub(crate) fn check(&self) -> Result<(), ServerError> {
+        if self.is_cancelled() {
+            Err("Request cancelled".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
 struct RequestHandler {
     id: RequestId,
-    //cancel: Arc<AtomicBool>,
+    cancel: Cancellation,
 }
 
 impl RequestHandler {
+    fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
     fn response<T: Serialize>(self, resp: Result<T, ServerError>) -> Result<()> {
+        // The client is not interested in the result anymore
+        if self.is_cancelled() {
+            return self.response_err(ErrorCode::RequestCanceled, "Request cancelled");
+        }
+        SERVER.pending_requests.ulock().remove(&self.id);
         SERVER.response(self.id, resp.map_err(|e| e.into()))
     }
 
     fn response_err(self, code: ErrorCode, message: impl Into<String>) -> Result<()> {
+        SERVER.pending_requests.ulock().remove(&self.id);
         SERVER.response::<Hover>(
             self.id,
             Err(ResponseError {
@@ -146,6 +237,9 @@ impl RequestHandler {
     }
 
     fn handle(self, req: RequestType) -> Result<()> {
+        if self.is_cancelled() {
+            return self.response_err(ErrorCode::RequestCanceled, "Request cancelled");
+        }
         let db = SERVER
             .workspace
             .lock()
@@ -155,17 +249,50 @@ impl RequestHandler {
             .db
             .clone();
         let vfs = &SERVER.vfs;
+        // The handler is consumed by the response, keep the cancellation flag for the long requests
+        let cancel = self.cancel.clone();
         match req {
+            RequestType::Completion(CompletionParams {
+                text_document_position:
+                    TextDocumentPositionParams {
+                        text_document: doc,
+                        position,
+                    },
+                ..
+            }) => self.response(completion(doc.uri.into(), position, vfs, db)),
+            RequestType::CompletionResolve(item) => {
+                let typesetting = SERVER.get_unicode_typesetting();
+                self.response(completion_resolve(*item, db, typesetting.as_deref()))
+            }
             RequestType::Hover(TextDocumentPositionParams {
                 text_document: doc,
                 position,
-            }) => self.response(hover(doc.uri.into(), position, vfs, db)),
+            }) => {
+                let typesetting = SERVER.get_typesetting();
+                let unicode = SERVER.options.ulock().unicode;
+                self.response(hover(
+                    doc.uri.into(),
+                    position,
+                    vfs,
+                    db,
+                    &typesetting,
+                    unicode,
+                ))
+            }
             RequestType::Definition(TextDocumentPositionParams {
                 text_document: doc,
                 position,
             }) => self.response(definition(doc.uri.into(), position, vfs, db)),
-            RequestType::ShowProof(ShowProofParams { label, .. }) => {
-                self.response(show_proof(label, vfs, db))
+            RequestType::ShowProof(ShowProofParams {
+                label, worksheet, ..
+            }) => {
+                // A worksheet to be edited is kept in ASCII, so that it can be parsed back
+                let typesetting = if worksheet {
+                    None
+                } else {
+                    SERVER.get_unicode_typesetting()
+                };
+                self.response(show_proof(label, vfs, db, typesetting.as_deref()))
             }
             RequestType::References(ReferenceParams {
                 text_document_position:
@@ -177,18 +304,121 @@ impl RequestHandler {
                 partial_result_params: _,
                 context:
                     ReferenceContext {
-                        include_declaration: _,
+                        include_declaration,
+                    },
+            }) => self.response(references(
+                doc.uri.into(),
+                position,
+                include_declaration,
+                vfs,
+                db,
+                &cancel,
+            )),
+            RequestType::DocumentSymbol(DocumentSymbolParams {
+                text_document: doc, ..
+            }) => self.response(outline(
+                doc.uri.into(),
+                SERVER.options.ulock().outline_statements,
+                vfs,
+                &db,
+            )),
+            RequestType::DocumentHighlight(DocumentHighlightParams {
+                text_document_position_params:
+                    TextDocumentPositionParams {
+                        text_document: doc,
+                        position,
                     },
-            }) => self.response(references(doc.uri.into(), position, vfs, db)),
-            RequestType::DocumentSymbol(DocumentSymbolParams { .. }) => {
-                self.response(outline(vfs, &db))
+                ..
+            }) => self.response(document_highlight(
+                doc.uri.into(),
+                position,
+                vfs,
+                db,
+                &cancel,
+            )),
+            RequestType::WorkspaceSymbol(WorkspaceSymbolParams { query, .. }) => {
+                self.response(workspace_symbols(query, vfs, db, &cancel))
             }
             RequestType::InlayHint(InlayHintParams {
                 text_document: doc,
                 range,
                 ..
-            }) => self.response(inlay_hints(doc.uri.into(), range, vfs, db)),
+            }) => self.response(inlay_hints(
+                doc.uri.into(),
+                range,
+                vfs,
+                db,
+                SERVER.get_unicode_typesetting(),
+            )),
+            RequestType::FoldingRange(FoldingRangeParams {
+                text_document: doc, ..
+            }) => self.response(folding_ranges(doc.uri.into(), vfs, db)),
+            RequestType::CodeLens(CodeLensParams {
+                text_document: doc, ..
+            }) => self.response(code_lens(
+                doc.uri.into(),
+                vfs,
+                db,
+                || SERVER.get_usages(&cancel),
+                &cancel,
+            )),
+            RequestType::CodeAction(CodeActionParams {
+                text_document: doc,
+                range,
+                ..
+            }) => self.response(code_actions(doc.uri.into(), range, vfs, db)),
+            RequestType::SemanticTokens(SemanticTokensParams {
+                text_document: doc, ..
+            }) => self.response(semantic_tokens(doc.uri.into(), None, vfs, db)),
+            RequestType::SemanticTokensRange(SemanticTokensRangeParams {
+                text_document: doc,
+                range,
+                ..
+            }) => self.response(semantic_tokens(doc.uri.into(), Some(range), vfs, db)),
             RequestType::ToggleDv => self.response(toggle_hints()),
+            RequestType::Unify(UnifyParams { text_document: doc }) => {
+                self.response(unify(doc.uri.into(), vfs, db))
+            }
+            RequestType::GenerateProof(GenerateProofParams {
+                text_document: doc,
+                format,
+            }) => self.response(generate_proof(
+                doc.uri.into(),
+                format.unwrap_or(SERVER.options.ulock().proof_format),
+                vfs,
+                db,
+            )),
+            RequestType::StoreProof(StoreProofParams {
+                text_document: doc,
+                format,
+            }) => self.response(store_proof(
+                doc.uri.into(),
+                format.unwrap_or(SERVER.options.ulock().proof_format),
+                vfs,
+                db,
+            )),
+            RequestType::PrepareRename(TextDocumentPositionParams {
+                text_document: doc,
+                position,
+            }) => self.response(prepare_rename(doc.uri.into(), position, vfs, db)),
+            RequestType::Rename(RenameParams {
+                text_document_position:
+                    TextDocumentPositionParams {
+                        text_document: doc,
+                        position,
+                    },
+                new_name,
+                ..
+            }) => self.response(rename(doc.uri.into(), position, new_name, vfs, db, &cancel)),
+            RequestType::Renumber(RenumberParams {
+                text_document: doc,
+                increment,
+            }) => self.response(renumber(
+                doc.uri.into(),
+                increment.unwrap_or(SERVER.options.ulock().renumber_increment),
+                vfs,
+                db,
+            )),
             _ => self.response_err(ErrorCode::MethodNotFound, "Not implemented"),
         }
     }
@@ -208,16 +438,67 @@ lazy_static! {
     pub static ref SERVER: Server = Server::new();
 }
 
+/// Builds the diagnostics of the given classes for the database, by file
+fn database_diagnostics(
+    db: &mut Database,
+    classes: &[DiagnosticClass],
+) -> HashMap<Url, Vec<Diagnostic>> {
+    let mm_diags = db.diag_notations(classes);
+    let lsp_diags = db.render_diags(mm_diags, make_lsp_diagnostic);
+    let mut diags = HashMap::new();
+    for (uri, diag) in lsp_diags.into_iter().flatten() {
+        diags.entry(uri).or_insert_with(Vec::new).push(diag);
+    }
+    diags
+}
+
+/// The names of the files of a database, as given to include them
+fn database_file_names(db: &Database) -> Vec<String> {
+    let mut file_names: Vec<String> = vec![];
+    for stmt in db.statements() {
+        let name = db.statement_source_name(stmt.address());
+        // Statements of the same file mostly follow each other
+        if file_names.last().map_or(true, |last| last != name)
+            && !file_names.iter().any(|file_name| file_name == name)
+        {
+            file_names.push(name.to_string());
+        }
+    }
+    file_names
+}
+
+/// The delay after the last edit of a database file, before the database is parsed again
+const REFRESH_DELAY: Duration = Duration::from_millis(500);
+
 pub struct Workspace {
     db: Database,
+    /// The options used to create the database, kept for reloading it
+    db_options: DbOptions,
+    /// The name of the main database file, which is parsed first
+    main_file: String,
+    /// The names of all the database files, as the database refers to them
+    file_names: Vec<String>,
     diags: HashMap<Url, Vec<Diagnostic>>,
     pub(crate) show_inlay_hints_dv: bool,
+    /// The typesetting definitions of the database, parsed on first use
+    typesetting: Option<Arc<Typesetting>>,
+    /// The usages of the labels of the database shown by code lenses, collected on first use
+    usages: Option<Arc<Usages>>,
+    /// Incremented each time the database is updated, to tell whether data collected
+    /// from a copy of the database is still current
+    db_generation: u64,
 }
 
 pub struct Server {
     pub workspace: Arc<Mutex<Option<Workspace>>>,
     pub vfs: Vfs,
     pub conn: Connection,
+    /// The options set in the workspace configuration
+    pub options: Mutex<ServerOptions>,
+    /// The worker threads handling the requests, so that the message loop is never blocked
+    pool: ThreadPool,
+    /// The cancellation flags of the requests being handled
+    pending_requests: Mutex<HashMap<RequestId, Cancellation>>,
 }
 
 impl Server {
@@ -227,6 +508,12 @@ impl Server {
             workspace: Arc::default(),
             vfs: Vfs::default(),
             conn,
+            options: Mutex::default(),
+            pool: ThreadPool::builder()
+                .name_prefix("mm-lsp-worker-")
+                .create()
+                .expect("could not create the request thread pool"),
+            pending_requests: Mutex::default(),
         }
     }
 
@@ -234,46 +521,193 @@ impl Server {
         self.workspace.lock().unwrap().as_ref().unwrap().db.clone()
     }
 
+    /// The typesetting definitions of the database, which are parsed again
+    /// after each update of the database
+    pub(crate) fn get_typesetting(&self) -> Arc<Typesetting> {
+        let mut guard = self.workspace.lock().unwrap();
+        let workspace = guard.as_mut().unwrap();
+        let db = &workspace.db;
+        workspace
+            .typesetting
+            .get_or_insert_with(|| Arc::new(Typesetting::new(db)))
+            .clone()
+    }
+
+    /// The usages of the labels of the database, which are collected again
+    /// after each update of the database
+    pub(crate) fn get_usages(&self, cancel: &Cancellation) -> Result<Arc<Usages>, ServerError> {
+        let (db, generation) = {
+            let guard = self.workspace.lock().unwrap();
+            let workspace = guard.as_ref().unwrap();
+            if let Some(usages) = &workspace.usages {
+                return Ok(usages.clone());
+            }
+            (workspace.db.clone(), workspace.db_generation)
+        };
+        // This goes through the whole database, so the workspace is not locked meanwhile
+        let usages = Arc::new(Usages::new(&db, cancel)?);
+        let mut guard = self.workspace.lock().unwrap();
+        let workspace = guard.as_mut().unwrap();
+        // The database may have been updated in the meantime
+        if workspace.db_generation == generation {
+            workspace.usages = Some(usages.clone());
+        }
+        Ok(usages)
+    }
+
+    /// The typesetting definitions to render formulas with, if Unicode rendering is enabled
+    pub(crate) fn get_unicode_typesetting(&self) -> Option<Arc<Typesetting>> {
+        let unicode = self.options.ulock().unicode;
+        unicode.then(|| self.get_typesetting())
+    }
+
     pub fn init(&self, options: DbOptions, file_name: &str) {
         let mut db = Database::new(options);
         db.parse(file_name.into(), Vec::new());
         db.outline_pass();
-        let mm_diags = db.diag_notations(&[
-            DiagnosticClass::Parse,
-            DiagnosticClass::Scope,
-            DiagnosticClass::Verify,
-            DiagnosticClass::Grammar,
-            DiagnosticClass::StmtParse,
-        ]);
-        let lsp_diags = db.render_diags(mm_diags, make_lsp_diagnostic);
-        let mut diags = HashMap::new();
-        for (uri, diag) in lsp_diags.into_iter().flatten() {
-            diags.entry(uri).or_insert_with(Vec::new).push(diag);
-        }
-        *self.workspace.lock().unwrap() = Some(Workspace {
+        let diag_classes = self.options.ulock().diagnostics.classes();
+        let diags = database_diagnostics(&mut db, &diag_classes);
+        let file_names = database_file_names(&db);
+        let mut guard = self.workspace.lock().unwrap();
+        let db_generation = guard
+            .as_ref()
+            .map_or(0, |workspace| workspace.db_generation + 1);
+        *guard = Some(Workspace {
             db,
+            db_options: options,
+            main_file: file_name.to_string(),
+            file_names,
             diags,
             show_inlay_hints_dv: false,
+            typesetting: None,
+            usages: None,
+            db_generation,
         });
+        drop(guard);
         self.log_message("Database loaded.".to_string()).ok();
     }
 
+    /// Parses the database again, using the texts of the database files being edited,
+    /// and publishes the updated diagnostics.
+    /// Since the database is incremental, only the modified segments are actually re-parsed.
+    pub(crate) fn refresh_database(&self) {
+        self.parse_database(None);
+    }
+
+    /// Parses the database and publishes the updated diagnostics.
+    /// If a new main file is given, a new database is created for it first.
+    fn parse_database(&self, new_main_file: Option<String>) {
+        let diag_classes = self.options.ulock().diagnostics.classes();
+        let stale_uris: Vec<Url> = {
+            let mut guard = self.workspace.lock().unwrap();
+            let workspace = guard.as_mut().unwrap();
+            if let Some(main_file) = new_main_file {
+                info!("Loading database {}", main_file);
+                workspace.db = Database::new(workspace.db_options);
+                workspace.file_names = vec![main_file.clone()];
+                workspace.main_file = main_file;
+            }
+            let texts = self.vfs.edited_mm_texts(&workspace.file_names);
+            workspace.db.parse(workspace.main_file.clone(), texts);
+            workspace.db.outline_pass();
+            workspace.file_names = database_file_names(&workspace.db);
+            workspace.typesetting = None;
+            workspace.usages = None;
+            workspace.db_generation += 1;
+            let diags = database_diagnostics(&mut workspace.db, &diag_classes);
+            let old_diags = std::mem::replace(&mut workspace.diags, diags);
+            old_diags
+                .into_keys()
+                .filter(|uri| !workspace.diags.contains_key(uri))
+                .collect()
+        };
+        // Clear the diagnostics of the files which do not have any anymore
+        for uri in stale_uris {
+            self.send_diagnostics(uri, None, vec![]).ok();
+        }
+        self.send_workspace_diagnostics();
+    }
+
+    /// Applies the options received from the workspace configuration.
+    /// The database is reloaded if its main file or the number of jobs changed,
+    /// and the diagnostics are recomputed if the enabled classes changed.
+    fn apply_options(&self, options: ServerOptions) {
+        let old_options = std::mem::replace(&mut *self.options.ulock(), options.clone());
+        let new_main_file = {
+            let mut guard = self.workspace.lock().unwrap();
+            let workspace = guard.as_mut().unwrap();
+            if options.dv_hints != old_options.dv_hints {
+                workspace.show_inlay_hints_dv = options.dv_hints;
+            }
+            let jobs_changed = options
+                .jobs
+                .map_or(false, |jobs| jobs != workspace.db_options.jobs);
+            if let Some(jobs) = options.jobs {
+                workspace.db_options.jobs = jobs;
+            }
+            match options.main_file_path {
+                Some(main_file) if !is_same_file(&main_file, &workspace.main_file) => {
+                    Some(main_file)
+                }
+                _ if jobs_changed => Some(workspace.main_file.clone()),
+                _ => None,
+            }
+        };
+        if new_main_file.is_some() {
+            self.parse_database(new_main_file);
+            self.log_message("Database loaded.".to_string()).ok();
+        } else if options.diagnostics != old_options.diagnostics {
+            self.refresh_database();
+        }
+    }
+
     pub(crate) fn start(&self) -> Result<()> {
         let _params: InitializeParams =
             from_value(self.conn.initialize(to_value(ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::INCREMENTAL,
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..Default::default()
+                    },
                 )),
                 hover_provider: Some(true.into()),
-                // completion_provider: Some(CompletionOptions {
-                //     resolve_provider: Some(true),
-                //     ..Default::default()
-                // }),
+                completion_provider: Some(CompletionOptions {
+                    resolve_provider: Some(true),
+                    trigger_characters: Some(vec![":".to_string(), "~".to_string()]),
+                    ..Default::default()
+                }),
                 definition_provider: Some(OneOf::Left(true)),
                 document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
                 references_provider: Some(OneOf::Left(true)),
-                // document_highlight_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
                 inlay_hint_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+                        ..Default::default()
+                    },
+                )),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            legend: crate::semantic_tokens::legend(),
+                            range: Some(true),
+                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                            ..Default::default()
+                        },
+                    ),
+                ),
                 ..Default::default()
             })?)?)?;
         Ok(())
@@ -328,6 +762,30 @@ impl Server {
         })
     }
 
+    /// Asks the client to notify us when database files are changed outside of the editor
+    fn register_file_watchers(&self) -> Result<()> {
+        use lsp_types::notification::{DidChangeWatchedFiles, Notification};
+        use lsp_types::request::{RegisterCapability, Request};
+        let params = RegistrationParams {
+            registrations: vec![Registration {
+                id: "watch_mm_files".to_string(),
+                method: DidChangeWatchedFiles::METHOD.to_string(),
+                register_options: Some(to_value(DidChangeWatchedFilesRegistrationOptions {
+                    watchers: vec![FileSystemWatcher {
+                        glob_pattern: "**/*.mm".to_string(),
+                        kind: None,
+                    }],
+                })?),
+            }],
+        };
+        let req = lsp_server::Request::new(
+            RequestId::from("register_watchers".to_string()),
+            RegisterCapability::METHOD.to_string(),
+            params,
+        );
+        self.send_message(req)
+    }
+
     fn send_config_request(&self) -> Result<()> {
         use lsp_types::request::{Request, WorkspaceConfiguration};
         let params = lsp_types::ConfigurationParams {
@@ -353,13 +811,33 @@ impl Server {
         if let Err(e) = self.send_config_request() {
             error!("Server panicked: {:?}", e);
         }
+        if let Err(e) = self.register_file_watchers() {
+            error!("Could not register file watchers: {:?}", e);
+        }
         // We already opened and parsed the Database, send the corresponding diagnostics
         self.send_workspace_diagnostics();
+        // When the database shall be parsed again, after edits of its files
+        let mut refresh_deadline: Option<Instant> = None;
 
         loop {
             match (|| -> Result<bool> {
-                match self.conn.receiver.recv() {
-                    Err(RecvError) => return Ok(true),
+                let message = match refresh_deadline {
+                    Some(deadline) => self
+                        .conn
+                        .receiver
+                        .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                    None => self
+                        .conn
+                        .receiver
+                        .recv()
+                        .map_err(|RecvError| RecvTimeoutError::Disconnected),
+                };
+                match message {
+                    Err(RecvTimeoutError::Disconnected) => return Ok(true),
+                    Err(RecvTimeoutError::Timeout) => {
+                        refresh_deadline = None;
+                        self.refresh_database();
+                    }
                     Ok(Message::Request(req)) => {
                         if self.conn.handle_shutdown(&req)? {
                             return Ok(true);
@@ -367,17 +845,24 @@ impl Server {
                         self.log_message(format!("Got Request: {}", req.method))
                             .ok();
                         if let Some((id, req)) = parse_request(req)? {
-                            let handler = RequestHandler { id };
-                            handler.handle(req)?;
-                            // Job::RequestHandler(id, Some(Box::new(req))).spawn();
+                            let cancel = Cancellation::default();
+                            self.pending_requests
+                                .ulock()
+                                .insert(id.clone(), cancel.clone());
+                            let handler = RequestHandler { id, cancel };
+                            self.pool.spawn_ok(async move {
+                                if let Err(e) = handler.handle(req) {
+                                    error!("Request failed: {:?}", e);
+                                }
+                            });
                         }
                     }
                     Ok(Message::Response(resp)) => {
                         if resp.id == get_config_id {
                             if let Some(val) = resp.result {
                                 info!("Set Option: {:?}", val);
-                                // let [config]: [ServerOptions; 1] = from_value(val)?;
-                                // *self.options.ulock() = config;
+                                let [config]: [ServerOptions; 1] = from_value(val)?;
+                                self.apply_options(config);
                             }
                         } else {
                             info!("Got response: {:?}", resp);
@@ -393,6 +878,20 @@ impl Server {
                         #[allow(clippy::wildcard_imports)]
                         use lsp_types::notification::*;
                         match notif.method.as_str() {
+                            Cancel::METHOD => {
+                                let CancelParams { id } = from_value(notif.params)?;
+                                let id: RequestId = match id {
+                                    NumberOrString::Number(id) => id.into(),
+                                    NumberOrString::String(id) => id.into(),
+                                };
+                                if let Some(cancel) = self.pending_requests.ulock().get(&id) {
+                                    cancel.cancel();
+                                }
+                            }
+                            DidChangeConfiguration::METHOD => {
+                                // The settings sent may be partial, query the whole section again
+                                self.send_config_request()?;
+                            }
                             DidOpenTextDocument::METHOD => {
                                 let db = self.get_database();
                                 let DidOpenTextDocumentParams { text_document: doc } =
@@ -412,7 +911,7 @@ impl Server {
                                     content_changes,
                                 } = from_value(notif.params)?;
                                 if !content_changes.is_empty() {
-                                    let path = doc.uri.clone().into();
+                                    let path: FileRef = doc.uri.clone().into();
                                     info!("change {:?}", path);
                                     let file =
                                         self.vfs.get(&path).ok_or("changed nonexistent file")?;
@@ -422,7 +921,49 @@ impl Server {
                                     if let Some((version, diagnotstics)) = file.diagnostics() {
                                         self.send_diagnostics(doc.uri, version, diagnotstics).ok();
                                     }
+                                    if path.has_extension("mm") {
+                                        // Wait for the user to pause typing before parsing again
+                                        refresh_deadline = Some(Instant::now() + REFRESH_DELAY);
+                                    }
+                                }
+                            }
+                            DidCloseTextDocument::METHOD => {
+                                let DidCloseTextDocumentParams { text_document: doc } =
+                                    from_value(notif.params)?;
+                                let path: FileRef = doc.uri.clone().into();
+                                info!("close {:?}", path);
+                                // Drop the editor's contents, the file will be read again from disk
+                                self.vfs.close(&path);
+                                if path.has_extension("mm") {
+                                    refresh_deadline = None;
+                                    self.refresh_database();
+                                } else {
+                                    self.send_diagnostics(doc.uri, None, vec![]).ok();
+                                }
+                            }
+                            DidSaveTextDocument::METHOD => {
+                                let DidSaveTextDocumentParams {
+                                    text_document: doc, ..
+                                } = from_value(notif.params)?;
+                                let path: FileRef = doc.uri.into();
+                                if path.has_extension("mm") {
+                                    refresh_deadline = None;
+                                    self.refresh_database();
+                                }
+                            }
+                            DidChangeWatchedFiles::METHOD => {
+                                let DidChangeWatchedFilesParams { changes } =
+                                    from_value(notif.params)?;
+                                for change in changes {
+                                    let path: FileRef = change.uri.into();
+                                    info!("changed on disk {:?}", path);
+                                    // Files opened in the editor are kept as they are
+                                    if self.vfs.get(&path).map_or(false, |file| !file.is_open()) {
+                                        self.vfs.close(&path);
+                                    }
                                 }
+                                refresh_deadline = None;
+                                self.refresh_database();
                             }
                             _ => {
                                 info!("Got notification: {:?}", notif);
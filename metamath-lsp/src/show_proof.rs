@@ -1,20 +1,50 @@
 //! Handles shoe proof requests
 
+use crate::proof::ProofWorksheet;
+use crate::typesetting::Typesetting;
 use crate::vfs::Vfs;
 use crate::ServerError;
 use metamath_knife::Database;
 
+/// Renders the formulas of the steps of a proof worksheet as Unicode text.
+/// The result is meant to be read, and can not be parsed back.
+fn render_unicode(
+    text: String,
+    typesetting: &Typesetting,
+    db: &Database,
+) -> Result<String, ServerError> {
+    let worksheet = ProofWorksheet::from_string(text.clone(), db)?;
+    let mut out = text;
+    // Formulas are replaced starting from the last one, so that the indices of the previous ones stay valid
+    for step_info in worksheet.steps.iter().rev() {
+        if let Some(formula_span) = step_info.step.formula_span() {
+            // The formula span extends to the end of the step, including the last line break
+            let formula = formula_span.as_ref(&step_info.source).trim_end();
+            let start = formula_span.as_range(step_info.byte_idx).start;
+            let rendered = typesetting.render(formula.split_ascii_whitespace().map(str::as_bytes));
+            out.replace_range(start..start + formula.len(), &rendered);
+        }
+    }
+    Ok(out)
+}
+
+/// Exports the proof of the given theorem as a proof worksheet.
+/// If typesetting definitions are given, its formulas are rendered as Unicode text,
+/// otherwise the worksheet is kept in ASCII so that it can be edited.
 pub(crate) fn show_proof(
     label: String,
     _vfs: &Vfs,
     db: Database,
+    typesetting: Option<&Typesetting>,
 ) -> Result<Option<String>, ServerError> {
     if let Some(stmt) = db.statement(label.as_bytes()) {
         let mut buf = Vec::new();
         db.export_mmp(stmt, &mut buf)?;
-        Ok(Some(
-            std::str::from_utf8(buf.as_slice()).unwrap().to_string(),
-        ))
+        let text = std::str::from_utf8(buf.as_slice()).unwrap().to_string();
+        match typesetting {
+            Some(typesetting) => Ok(Some(render_unicode(text, typesetting, &db)?)),
+            None => Ok(Some(text)),
+        }
     } else {
         Ok(None)
     }
@@ -0,0 +1,25 @@
+use crate::show_proof::show_proof;
+use crate::test_fixtures::{open_db, TEST_DB};
+use crate::typesetting::Typesetting;
+
+#[test]
+fn show_proof_as_unicode_or_worksheet() {
+    let text = format!(
+        "{}th1 $p |- ( ph -> ( ph -> ph ) ) $= wph wph ax-1 $.\n$( $t althtmldef \"->\" as ' &rarr; '; $)\n",
+        TEST_DB
+    );
+    let (_, vfs, db) = open_db("show_proof.mm", &text);
+    let typesetting = Typesetting::new(&db);
+    let unicode = show_proof("th1".to_string(), &vfs, db.clone(), Some(&typesetting)).unwrap();
+    let unicode = unicode.unwrap();
+    assert!(unicode.contains("|- ( ph → ( ph → ph ) )"), "{}", unicode);
+    // A worksheet is kept in ASCII, so that it can be edited
+    let worksheet = show_proof("th1".to_string(), &vfs, db.clone(), None).unwrap();
+    let worksheet = worksheet.unwrap();
+    assert!(
+        worksheet.contains("|- ( ph -> ( ph -> ph ) )"),
+        "{}",
+        worksheet
+    );
+    assert_eq!(show_proof("th2".to_string(), &vfs, db, None).unwrap(), None);
+}
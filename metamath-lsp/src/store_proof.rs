@@ -0,0 +1,188 @@
+//! Handles requests to store the proof of a worksheet into the database
+
+use crate::definition::span_range;
+use crate::proof::{ProofFormat, ProofWorksheet};
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use memchr::memmem;
+use metamath_knife::statement::as_str;
+use metamath_knife::Database;
+use metamath_knife::Span;
+use metamath_knife::StatementRef;
+use metamath_knife::StatementType;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Indentation of the statements inside a `${ ... $}` block, and of proofs after their statement
+const BLOCK_INDENT: usize = 2;
+
+/// The URL of the file containing the given statement
+fn statement_url(stmt: StatementRef<'_>, db: &Database) -> Result<Url, ServerError> {
+    let path: PathBuf = db.statement_source_name(stmt.address()).into();
+    Url::from_file_path(path.canonicalize()?).map_err(|_| "Invalid database file path".into())
+}
+
+/// The column at which the given statement starts
+fn statement_column(stmt: StatementRef<'_>, vfs: &Vfs, db: &Database) -> usize {
+    span_range(stmt, stmt.span(), vfs, db).map_or(0, |range| range.start.character as usize)
+}
+
+/// Builds the edit replacing the proof of an existing theorem
+fn replace_proof_edit(
+    worksheet: &ProofWorksheet,
+    stmt: StatementRef<'_>,
+    format: ProofFormat,
+    vfs: &Vfs,
+    db: &Database,
+) -> Result<TextEdit, ServerError> {
+    if stmt.statement_type() != StatementType::Provable {
+        return Err("The theorem is not a $p statement".into());
+    }
+    let end = stmt.span().end as usize;
+    let (span, separator) = if stmt.proof_len() == 0 {
+        // The proof is empty, it is written just after the `$=` keyword
+        let buf = &stmt.segment().segment.buffer;
+        let start = memmem::rfind(stmt.span().as_ref(buf), b"$=")
+            .ok_or("The theorem has no $= keyword")?
+            + stmt.span().start as usize
+            + 2;
+        (Span::new(start, end), " ")
+    } else {
+        (Span::new(stmt.proof_span(0).start as usize, end), "")
+    };
+    let range = span_range(stmt, span, vfs, db).ok_or("Could not locate the proof")?;
+    let path: PathBuf = db.statement_source_name(stmt.address()).into();
+    let line = vfs.source(path.into(), db)?.line(range.start.line);
+    let column = range.start.character as usize + separator.len();
+    // Continuation lines are aligned with the proof if it starts on its own line
+    let indent = if line[..column.min(line.len())].trim().is_empty() {
+        column
+    } else {
+        statement_column(stmt, vfs, db) + BLOCK_INDENT
+    };
+    Ok(TextEdit {
+        range,
+        new_text: format!(
+            "{}{}",
+            separator,
+            worksheet.generate_proof_tokens(format, column, indent)?
+        ),
+    })
+}
+
+/// Builds the text of a new theorem, with its hypotheses, comment and proof
+fn new_theorem_text(
+    worksheet: &ProofWorksheet,
+    format: ProofFormat,
+    column: usize,
+) -> Result<String, ServerError> {
+    let qed = worksheet
+        .qed_step()
+        .ok_or("The proof worksheet has no steps")?;
+    let hyps: Vec<_> = (0..worksheet.steps.len())
+        .filter(|&step_idx| worksheet.steps[step_idx].step.is_hyp())
+        .collect();
+    let distinct_vars = worksheet.distinct_vars();
+    // Hypotheses and distinct variable conditions are scoped in a block with the theorem
+    let has_block = !hyps.is_empty() || !distinct_vars.is_empty();
+    let block_indent = " ".repeat(column);
+    let stmt_column = if has_block {
+        column + BLOCK_INDENT
+    } else {
+        column
+    };
+    let stmt_indent = " ".repeat(stmt_column);
+    let mut text = String::from("\n");
+    if has_block {
+        text.push_str(&format!("\n{block_indent}${{"));
+    }
+    for vars in distinct_vars {
+        text.push_str(&format!("\n{stmt_indent}$d {} $.", vars.join(" ")));
+    }
+    for step_idx in hyps.iter().copied() {
+        let formula = worksheet
+            .step_formula(step_idx)
+            .ok_or("A hypothesis step has no formula")?;
+        text.push_str(&format!(
+            "\n{stmt_indent}{} $e {} $.",
+            as_str(worksheet.step_label(step_idx)),
+            worksheet.formula_string(formula)
+        ));
+    }
+    if let Some(comment) = worksheet.comment() {
+        let comment = comment.replace('\n', &format!("\n{stmt_indent}   "));
+        text.push_str(&format!("\n{stmt_indent}$( {comment} $)"));
+    }
+    let formula = worksheet
+        .step_formula(qed)
+        .ok_or("The last step has no formula")?;
+    let proof_column = stmt_column + BLOCK_INDENT;
+    text.push_str(&format!(
+        "\n{stmt_indent}{} $p {} $=\n{}{}",
+        worksheet.theorem_label,
+        worksheet.formula_string(formula),
+        " ".repeat(proof_column),
+        worksheet.generate_proof_tokens(format, proof_column, proof_column)?
+    ));
+    if has_block {
+        text.push_str(&format!("\n{block_indent}$}}"));
+    }
+    Ok(text)
+}
+
+/// Builds the edit inserting a new theorem after the `LOC_AFTER` statement
+fn insert_theorem_edit(
+    worksheet: &ProofWorksheet,
+    stmt: StatementRef<'_>,
+    format: ProofFormat,
+    vfs: &Vfs,
+    db: &Database,
+) -> Result<TextEdit, ServerError> {
+    let end = stmt.span().end as usize;
+    let range =
+        span_range(stmt, Span::new(end, end), vfs, db).ok_or("Could not locate LOC_AFTER")?;
+    Ok(TextEdit {
+        range,
+        new_text: new_theorem_text(worksheet, format, statement_column(stmt, vfs, db))?,
+    })
+}
+
+pub(crate) fn store_proof(
+    path: FileRef,
+    format: ProofFormat,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Option<WorkspaceEdit>, ServerError> {
+    let worksheet = match vfs.source(path, &db)? {
+        FileContents::MMPFile(worksheet) => worksheet,
+        FileContents::MMFile(_) => return Ok(None),
+    };
+    let (stmt, edit) = if let Some(sadd) = worksheet.sadd {
+        let stmt = db.statement_by_address(sadd);
+        (
+            stmt,
+            replace_proof_edit(&worksheet, stmt, format, vfs, &db)?,
+        )
+    } else {
+        if worksheet.theorem_label.is_empty() {
+            return Err("The proof worksheet has no THEOREM label".into());
+        }
+        let loc_after = worksheet
+            .loc_after
+            .ok_or("A LOC_AFTER statement is required to add a new theorem")?;
+        let stmt = db.statement_by_address(loc_after);
+        (
+            stmt,
+            insert_theorem_edit(&worksheet, stmt, format, vfs, &db)?,
+        )
+    };
+    let mut changes = HashMap::new();
+    changes.insert(statement_url(stmt, &db)?, vec![edit]);
+    Ok(Some(WorkspaceEdit {
+        changes: Some(changes),
+        ..WorkspaceEdit::default()
+    }))
+}
@@ -0,0 +1,58 @@
+use crate::proof::ProofFormat;
+use crate::store_proof::store_proof;
+use crate::test_fixtures::{open_db, open_worksheet, single_edit, TEST_DB};
+use lsp_types::*;
+
+#[test]
+fn store_proof_into_empty_proof() {
+    let text = TEST_DB.replace("$= ? $.", "$= $.");
+    let (_, vfs, db) = open_db("store_empty_proof.mm", &text);
+    let worksheet = open_worksheet(
+        "store_empty_proof.mmp",
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1      |- ph
+2::ax-1        |- ( ph -> ( ps -> ph ) )
+qed:1,2:ax-mp  |- ( ps -> ph )
+",
+        &vfs,
+        &db,
+    );
+    let edit = single_edit(store_proof(worksheet, ProofFormat::Normal, &vfs, db).unwrap());
+    assert_eq!(
+        edit.range,
+        Range::new(Position::new(13, 29), Position::new(13, 32))
+    );
+    assert_eq!(edit.new_text, " wph wps wph wi a1i.1 wph wps ax-1 ax-mp $.");
+}
+
+#[test]
+fn store_proof_new_theorem_with_distinct_vars() {
+    let (_, vfs, db) = open_db("store_new_theorem.mm", TEST_DB);
+    let worksheet = open_worksheet(
+        "store_new_theorem.mmp",
+        "$( <MM> <PROOF_ASST> THEOREM=th1  LOC_AFTER=ax-1
+
+qed::ax-1      |- ( ph -> ( ph -> ph ) )
+
+$d ph ps
+",
+        &vfs,
+        &db,
+    );
+    let edit = single_edit(store_proof(worksheet, ProofFormat::Normal, &vfs, db).unwrap());
+    assert_eq!(
+        edit.range,
+        Range::new(Position::new(10, 36), Position::new(10, 36))
+    );
+    assert_eq!(
+        edit.new_text,
+        "
+
+${
+  $d ph ps $.
+  th1 $p |- ( ph -> ( ph -> ph ) ) $=
+    wph wph ax-1 $.
+$}"
+    );
+}
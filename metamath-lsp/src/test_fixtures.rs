@@ -0,0 +1,67 @@
+//! Shared fixtures for the tests of the language features
+
+use crate::util::FileRef;
+use crate::vfs::Vfs;
+use lsp_types::*;
+use metamath_knife::database::DbOptions;
+use metamath_knife::Database;
+
+pub(crate) const TEST_DB: &str = "$c |- wff ( ) -> $.
+$v ph ps $.
+wph $f wff ph $.
+wps $f wff ps $.
+wi $a wff ( ph -> ps ) $.
+${
+    min $e |- ph $.
+    maj $e |- ( ph -> ps ) $.
+    ax-mp $a |- ps $.
+$}
+ax-1 $a |- ( ph -> ( ps -> ph ) ) $.
+${
+    a1i.1 $e |- ph $.
+    a1i $p |- ( ps -> ph ) $= ? $.
+$}
+";
+
+/// Writes the given database text into a temporary file with the given name, parses it,
+/// and opens it in a virtual file system.
+/// Each test uses its own file name, since tests are run in parallel.
+pub(crate) fn open_db(name: &str, text: &str) -> (FileRef, Vfs, Database) {
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, text).unwrap();
+    let options = DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    };
+    let mut db = Database::new(options);
+    let file_name = path.to_str().unwrap().to_string();
+    db.parse(
+        file_name.clone(),
+        vec![(file_name, text.as_bytes().to_vec())],
+    );
+    db.grammar_pass();
+    db.stmt_parse_pass();
+    db.outline_pass();
+    let path = FileRef::from(path);
+    let vfs = Vfs::default();
+    vfs.open_virt(path.clone(), 1, text.to_string(), db.clone())
+        .unwrap();
+    (path, vfs, db)
+}
+
+/// Opens a proof worksheet in the given virtual file system
+pub(crate) fn open_worksheet(name: &str, text: &str, vfs: &Vfs, db: &Database) -> FileRef {
+    let path = FileRef::from(Url::from_file_path(std::env::temp_dir().join(name)).unwrap());
+    vfs.open_virt(path.clone(), 1, text.to_string(), db.clone())
+        .unwrap();
+    path
+}
+
+/// The single text edit of a workspace edit
+pub(crate) fn single_edit(edit: Option<WorkspaceEdit>) -> TextEdit {
+    let mut changes: Vec<_> = edit.unwrap().changes.unwrap().into_values().collect();
+    assert_eq!(changes.len(), 1);
+    let mut edits = changes.pop().unwrap();
+    assert_eq!(edits.len(), 1);
+    edits.pop().unwrap()
+}
@@ -1,6 +1,8 @@
 //! Additional types used on the LSP interface
 
+use crate::proof::ProofFormat;
 use lsp_types::{Range, TextDocumentIdentifier};
+use metamath_knife::diag::DiagnosticClass;
 use serde::{Deserialize, Serialize};
 
 /// A parameter literal used in inlay hint requests.
@@ -17,4 +19,143 @@ pub struct ShowProofParams {
 
     /// The visible document range for which inlay hints should be computed.
     pub range: Range,
+
+    /// Whether the proof is shown as a proof worksheet to be edited,
+    /// in which case its formulas are never rendered as Unicode text.
+    #[serde(default)]
+    pub worksheet: bool,
+}
+
+/// Parameters for the unification of a proof worksheet.
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnifyParams {
+    /// The proof worksheet to unify.
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// Parameters for the generation of the proof of a proof worksheet.
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateProofParams {
+    /// The proof worksheet for which to generate the proof.
+    pub text_document: TextDocumentIdentifier,
+
+    /// The format of the generated proof, taken from the configuration by default.
+    pub format: Option<ProofFormat>,
+}
+
+/// Parameters for storing the proof of a proof worksheet into the database.
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreProofParams {
+    /// The proof worksheet whose proof shall be stored.
+    pub text_document: TextDocumentIdentifier,
+
+    /// The format of the stored proof, taken from the configuration by default.
+    pub format: Option<ProofFormat>,
+}
+
+/// Parameters for renumbering the steps of a proof worksheet.
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenumberParams {
+    /// The proof worksheet whose steps shall be renumbered.
+    pub text_document: TextDocumentIdentifier,
+
+    /// The increment between step numbers, taken from the configuration by default.
+    pub increment: Option<usize>,
+}
+
+/// The diagnostic classes reported for the database.
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiagnosticOptions {
+    /// Report parse errors.
+    pub parse: bool,
+
+    /// Report scope errors, like undeclared or duplicate labels.
+    pub scope: bool,
+
+    /// Report proof verification errors.
+    pub verify: bool,
+
+    /// Report grammar errors.
+    pub grammar: bool,
+
+    /// Report statement parse errors.
+    pub stmt_parse: bool,
+}
+
+impl Default for DiagnosticOptions {
+    fn default() -> Self {
+        Self {
+            parse: true,
+            scope: true,
+            verify: true,
+            grammar: true,
+            stmt_parse: true,
+        }
+    }
+}
+
+impl DiagnosticOptions {
+    /// The enabled diagnostic classes
+    pub fn classes(&self) -> Vec<DiagnosticClass> {
+        [
+            (self.parse, DiagnosticClass::Parse),
+            (self.scope, DiagnosticClass::Scope),
+            (self.verify, DiagnosticClass::Verify),
+            (self.grammar, DiagnosticClass::Grammar),
+            (self.stmt_parse, DiagnosticClass::StmtParse),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, class)| enabled.then(|| class))
+        .collect()
+    }
+}
+
+/// The options of the server, taken from the `metamath` section of the workspace configuration.
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServerOptions {
+    /// The main database file. The database is reloaded when it changes.
+    pub main_file_path: Option<String>,
+
+    /// The number of threads used to parse the database.
+    pub jobs: Option<usize>,
+
+    /// Whether distinct variable hints are shown initially.
+    pub dv_hints: bool,
+
+    /// The diagnostic classes reported.
+    pub diagnostics: DiagnosticOptions,
+
+    /// The format of the proofs generated from proof worksheets.
+    pub proof_format: ProofFormat,
+
+    /// The increment between step numbers when renumbering proof worksheets.
+    pub renumber_increment: usize,
+
+    /// Whether the outline of database files also lists statements, besides chapters and sections.
+    pub outline_statements: bool,
+
+    /// Whether formulas are rendered as Unicode text in hovers, shown proofs and inlay hints,
+    /// using the typesetting definitions of the database.
+    pub unicode: bool,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            main_file_path: None,
+            jobs: None,
+            dv_hints: false,
+            diagnostics: DiagnosticOptions::default(),
+            proof_format: ProofFormat::default(),
+            renumber_increment: 1,
+            outline_statements: false,
+            unicode: false,
+        }
+    }
 }
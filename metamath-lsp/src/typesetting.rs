@@ -0,0 +1,256 @@
+//! Parses the typesetting definitions found in the `$t` comments of the database.
+//! The `althtmldef` definitions give the HTML rendering of math tokens using Unicode characters,
+//! from which a plain text rendering is derived.
+
+use metamath_knife::statement::as_str;
+use metamath_knife::statement::TokenPtr;
+use metamath_knife::Database;
+use metamath_knife::StatementType;
+use std::collections::HashMap;
+
+/// A token of a typesetting comment
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum TsToken {
+    /// A keyword, like `althtmldef` or `as`
+    Keyword(String),
+    /// A quoted string, with its quotes removed
+    String(String),
+    /// The `+` concatenation operator
+    Plus,
+    /// The `;` ending each definition
+    Semicolon,
+}
+
+/// Splits the text of a typesetting comment into tokens, skipping the C-style comments.
+/// Strings are delimited by simple or double quotes; a quote doubled within a string stands for itself.
+pub(crate) fn tokenize(text: &str) -> Vec<TsToken> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            _ if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '"' | '\'' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some(d) if d == c => {
+                            if chars.peek() == Some(&c) {
+                                chars.next();
+                                string.push(c);
+                            } else {
+                                break;
+                            }
+                        }
+                        Some(d) => string.push(d),
+                        None => break,
+                    }
+                }
+                tokens.push(TsToken::String(string));
+            }
+            '+' => tokens.push(TsToken::Plus),
+            ';' => tokens.push(TsToken::Semicolon),
+            _ => {
+                let mut keyword = c.to_string();
+                while let Some(&d) = chars.peek() {
+                    if d.is_whitespace() || matches!(d, ';' | '"' | '\'') {
+                        break;
+                    }
+                    keyword.push(d);
+                    chars.next();
+                }
+                tokens.push(TsToken::Keyword(keyword));
+            }
+        }
+    }
+    tokens
+}
+
+/// Parses the definitions `<keyword> "<token>" as "<string>" [+ "<string>" ...];`
+/// for the given keyword. Other definitions are ignored.
+pub(crate) fn parse_definitions(
+    tokens: &[TsToken],
+    keyword: &str,
+    defs: &mut HashMap<Vec<u8>, String>,
+) {
+    for definition in tokens.split(|token| *token == TsToken::Semicolon) {
+        if let [TsToken::Keyword(kw), TsToken::String(token), TsToken::Keyword(as_kw), rest @ ..] =
+            definition
+        {
+            if kw != keyword || as_kw != "as" {
+                continue;
+            }
+            let mut value = String::new();
+            for part in rest {
+                match part {
+                    TsToken::String(string) => value.push_str(string),
+                    TsToken::Plus => {}
+                    _ => break,
+                }
+            }
+            defs.insert(token.as_bytes().to_vec(), value);
+        }
+    }
+}
+
+/// Decodes an HTML character entity, given without its `&` and `;` delimiters.
+/// Only numeric entities and the named entities commonly found in databases are supported.
+pub(crate) fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code = match number.strip_prefix(|c: char| c == 'x' || c == 'X') {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "not" => '¬',
+        "and" => '∧',
+        "or" => '∨',
+        "rarr" => '→',
+        "larr" => '←',
+        "harr" => '↔',
+        "rArr" => '⇒',
+        "hArr" => '⇔',
+        "forall" => '∀',
+        "exist" => '∃',
+        "isin" => '∈',
+        "notin" => '∉',
+        "ne" => '≠',
+        "le" => '≤',
+        "ge" => '≥',
+        "sub" => '⊂',
+        "sube" => '⊆',
+        "sup" => '⊃',
+        "supe" => '⊇',
+        "cup" => '∪',
+        "cap" => '∩',
+        "empty" => '∅',
+        "times" => '×',
+        "middot" => '·',
+        "infin" => '∞',
+        "prop" => '∝',
+        "sum" => '∑',
+        "prod" => '∏',
+        "minus" => '−',
+        "lambda" => 'λ',
+        "omega" => 'ω',
+        "phi" => 'φ',
+        "psi" => 'ψ',
+        "chi" => 'χ',
+        "theta" => 'θ',
+        "tau" => 'τ',
+        "eta" => 'η',
+        "zeta" => 'ζ',
+        "sigma" => 'σ',
+        "rho" => 'ρ',
+        "mu" => 'μ',
+        "iota" => 'ι',
+        "lang" | "langle" => '⟨',
+        "rang" | "rangle" => '⟩',
+        "lceil" => '⌈',
+        "rceil" => '⌉',
+        "lfloor" => '⌊',
+        "rfloor" => '⌋',
+        _ => None?,
+    })
+}
+
+/// Converts HTML into plain text, removing the tags and decoding the character entities.
+/// Unknown entities are kept as they are.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(idx) = rest.find(|c: char| c == '<' || c == '&') {
+        text.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        if rest.starts_with('<') {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+        } else {
+            match rest
+                .find(';')
+                .and_then(|end| Some((decode_entity(&rest[1..end])?, end)))
+            {
+                Some((c, end)) => {
+                    text.push(c);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    text.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+    }
+    text.push_str(rest);
+    text.trim().to_string()
+}
+
+/// The typesetting definitions of a database
+#[derive(Debug, Default)]
+pub(crate) struct Typesetting {
+    /// The `althtmldef` definitions, by math token
+    alt_html_defs: HashMap<Vec<u8>, String>,
+}
+
+impl Typesetting {
+    /// Parses all the typesetting comments of the database
+    pub(crate) fn new(db: &Database) -> Self {
+        let mut alt_html_defs = HashMap::new();
+        for stmt in db.statements() {
+            if stmt.statement_type() != StatementType::TypesettingComment {
+                continue;
+            }
+            let buf = &stmt.segment().segment.buffer;
+            let text = String::from_utf8_lossy(stmt.span().as_ref(buf));
+            // Skip the `$( $t` opening and `$)` closing tokens
+            let text = text.trim();
+            let text = text.strip_prefix("$(").unwrap_or(text).trim_start();
+            let text = text.strip_prefix("$t").unwrap_or(text);
+            let text = text.strip_suffix("$)").unwrap_or(text);
+            parse_definitions(&tokenize(text), "althtmldef", &mut alt_html_defs);
+        }
+        Self { alt_html_defs }
+    }
+
+    /// The `althtmldef` HTML rendering of a math token, if defined
+    pub(crate) fn alt_html(&self, token: TokenPtr<'_>) -> Option<&str> {
+        self.alt_html_defs.get(token).map(String::as_str)
+    }
+
+    /// The Unicode text rendering of a math token, if defined
+    pub(crate) fn unicode(&self, token: TokenPtr<'_>) -> Option<String> {
+        self.alt_html(token).map(html_to_text)
+    }
+
+    /// Renders a math token as Unicode text, or keeps it as it is if it has no definition
+    pub(crate) fn render_token(&self, token: TokenPtr<'_>) -> String {
+        self.unicode(token)
+            .filter(|text| !text.is_empty())
+            .unwrap_or_else(|| as_str(token).to_string())
+    }
+
+    /// Renders a sequence of math tokens as Unicode text, separated by spaces
+    pub(crate) fn render<'a>(&self, tokens: impl IntoIterator<Item = TokenPtr<'a>>) -> String {
+        tokens
+            .into_iter()
+            .map(|token| self.render_token(token))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
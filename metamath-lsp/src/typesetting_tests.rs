@@ -0,0 +1,110 @@
+use crate::test_fixtures::{open_db, TEST_DB};
+use crate::typesetting::{
+    decode_entity, html_to_text, parse_definitions, tokenize, TsToken, Typesetting,
+};
+use std::collections::HashMap;
+
+fn keyword(keyword: &str) -> TsToken {
+    TsToken::Keyword(keyword.to_string())
+}
+
+fn string(string: &str) -> TsToken {
+    TsToken::String(string.to_string())
+}
+
+#[test]
+fn tokenize_strings() {
+    assert_eq!(
+        tokenize(r#"althtmldef "->" as '&rarr;';"#),
+        vec![
+            keyword("althtmldef"),
+            string("->"),
+            keyword("as"),
+            string("&rarr;"),
+            TsToken::Semicolon,
+        ]
+    );
+    // A doubled quote stands for itself, the other quote is kept as it is
+    assert_eq!(
+        tokenize(r#""a""b" 'c''d"e'"#),
+        vec![string(r#"a"b"#), string(r#"c'd"e"#)]
+    );
+    // An unterminated string ends with the text
+    assert_eq!(tokenize(r#""abc"#), vec![string("abc")]);
+}
+
+#[test]
+fn tokenize_comments_and_concatenation() {
+    assert_eq!(
+        tokenize("htmldef /* \"not a string\"; */ \"A\" as \"<i>\" +\n  \"A\" + \"</i>\"; /**/"),
+        vec![
+            keyword("htmldef"),
+            string("A"),
+            keyword("as"),
+            string("<i>"),
+            TsToken::Plus,
+            string("A"),
+            TsToken::Plus,
+            string("</i>"),
+            TsToken::Semicolon,
+        ]
+    );
+}
+
+#[test]
+fn parse_definitions_of_keyword() {
+    let tokens = tokenize(
+        r#"althtmldef "A" as "<i>" + "a" + "</i>";
+        htmldef "B" as "b";
+        althtmldef "C" /* the C token */ as 'c';
+        althtmldef "D" "d";"#,
+    );
+    let mut defs = HashMap::new();
+    parse_definitions(&tokens, "althtmldef", &mut defs);
+    assert_eq!(defs.len(), 2);
+    assert_eq!(defs[b"A".as_slice()], "<i>a</i>");
+    assert_eq!(defs[b"C".as_slice()], "c");
+}
+
+#[test]
+fn decode_entities() {
+    assert_eq!(decode_entity("amp"), Some('&'));
+    assert_eq!(decode_entity("forall"), Some('∀'));
+    assert_eq!(decode_entity("#8594"), Some('→'));
+    assert_eq!(decode_entity("#x2192"), Some('→'));
+    assert_eq!(decode_entity("#X2192"), Some('→'));
+    assert_eq!(decode_entity("unknown"), None);
+    assert_eq!(decode_entity("#xZZ"), None);
+    assert_eq!(decode_entity("#"), None);
+}
+
+#[test]
+fn html_to_plain_text() {
+    assert_eq!(html_to_text("&rarr;"), "→");
+    assert_eq!(
+        html_to_text(r#"<span class="hidden">&forall;</span><i>x</i>"#),
+        "∀x"
+    );
+    assert_eq!(html_to_text(" &nbsp;a&lt;b&#62;c "), "a<b>c");
+    // Unknown entities, and ampersands which do not start an entity, are kept
+    assert_eq!(html_to_text("&foo; &amp"), "&foo; &amp");
+    assert_eq!(html_to_text("a & b"), "a & b");
+    // An unterminated tag is dropped
+    assert_eq!(html_to_text("a<b"), "a");
+}
+
+#[test]
+fn typesetting_of_database() {
+    let text = format!(
+        "{}$( $t\n  althtmldef \"->\" as ' &rarr; ';\n  htmldef \"->\" as \"-&gt;\";\n$)\n",
+        TEST_DB
+    );
+    let (_, _, db) = open_db("typesetting.mm", &text);
+    let typesetting = Typesetting::new(&db);
+    assert_eq!(typesetting.alt_html(b"->"), Some(" &rarr; "));
+    assert_eq!(typesetting.alt_html(b"ph"), None);
+    assert_eq!(
+        typesetting.render([b"(".as_slice(), b"ph", b"->", b"ps"]),
+        "( ph → ps"
+    );
+}
@@ -0,0 +1,31 @@
+//! Handles unification requests
+
+use crate::util::FileRef;
+use crate::vfs::FileContents;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::Database;
+use std::collections::HashMap;
+
+pub(crate) fn unify(
+    path: FileRef,
+    vfs: &Vfs,
+    db: Database,
+) -> Result<Option<WorkspaceEdit>, ServerError> {
+    let uri = path.url().clone();
+    if let FileContents::MMPFile(worksheet) = vfs.source(path, &db)? {
+        let edits = worksheet.unify();
+        if edits.is_empty() {
+            return Ok(None);
+        }
+        let mut changes = HashMap::new();
+        changes.insert(uri, edits);
+        Ok(Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..WorkspaceEdit::default()
+        }))
+    } else {
+        Ok(None)
+    }
+}
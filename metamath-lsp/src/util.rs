@@ -105,6 +105,16 @@ fn make_absolute(path: &str) -> PathBuf {
     std::fs::canonicalize(path).expect("Bad file path")
 }
 
+/// Returns true if both file names, relative to [`struct@CURRENT_DIR`], refer to the same file.
+/// Names of files which cannot be found are compared as they are.
+#[must_use]
+pub fn is_same_file(name1: &str, name2: &str) -> bool {
+    match (std::fs::canonicalize(name1), std::fs::canonicalize(name2)) {
+        (Ok(path1), Ok(path2)) => path1 == path2,
+        _ => name1 == name2,
+    }
+}
+
 #[derive(Default)]
 struct FileRefInner {
     path: PathBuf,
@@ -199,6 +209,18 @@ impl FileRef {
         Arc::ptr_eq(&self.0, &other.0)
     }
 
+    /// Returns true if this file is the one with the given name, relative to [`struct@CURRENT_DIR`],
+    /// like the file names of the database. Both are compared as canonical paths,
+    /// or as they are if either cannot be found.
+    #[must_use]
+    pub fn is_named(&self, name: &str) -> bool {
+        let path = CURRENT_DIR.join(name);
+        match (path.canonicalize(), self.path().canonicalize()) {
+            (Ok(path1), Ok(path2)) => path1 == path2,
+            _ => path == *self.path(),
+        }
+    }
+
     /// Returns true if this file has the provided extension.
     #[must_use]
     pub fn has_extension(&self, ext: &str) -> bool {
@@ -0,0 +1,21 @@
+use crate::test_fixtures::{open_db, TEST_DB};
+use crate::util::is_same_file;
+
+#[test]
+fn main_file_names_compared_as_paths() {
+    let (path, _, _) = open_db("main_file_name.mm", TEST_DB);
+    let name = path.path().to_str().unwrap();
+    let dir = path.path().parent().unwrap().to_str().unwrap();
+    assert!(is_same_file(name, &format!("{}/./main_file_name.mm", dir)));
+    assert!(!is_same_file(name, "missing.mm"));
+}
+
+#[test]
+fn file_named_with_a_non_canonical_path() {
+    let (path, _, _) = open_db("file_named.mm", TEST_DB);
+    let dir = path.path().parent().unwrap();
+    let dir_name = dir.file_name().unwrap().to_str().unwrap();
+    let name = format!("{}/../{}/file_named.mm", dir.to_str().unwrap(), dir_name);
+    assert!(path.is_named(&name));
+    assert!(!path.is_named("missing.mm"));
+}
@@ -101,8 +101,28 @@ impl VirtualFile {
         let (version, contents) = &mut *self.contents.ulock();
         *version = Some(new_version);
         match contents {
-            FileContents::MMFile(_text) => {}
-            FileContents::MMPFile(text) => Arc::get_mut(text).unwrap().apply_change(change),
+            FileContents::MMFile(text) => match text.change_event_to_rope_delta(change) {
+                Ok(delta) => *text = delta.apply(text),
+                Err(e) => error!("Could not apply change: {:?}", e),
+            },
+            // Requests may still hold the previous version of the worksheet, in which case it is copied
+            FileContents::MMPFile(text) => Arc::make_mut(text).apply_change(change),
+        }
+    }
+
+    /// Whether this file is opened in the editor, as opposed to read from disk
+    pub fn is_open(&self) -> bool {
+        self.contents.ulock().0.is_some()
+    }
+
+    /// The text of this file if it is a Metamath database file opened in the editor,
+    /// which may differ from the file on disk
+    fn edited_mm_text(&self) -> Option<Vec<u8>> {
+        match &*self.contents.ulock() {
+            (Some(_), FileContents::MMFile(text)) => {
+                Some(text.cow_for_range(..).into_owned().into_bytes())
+            }
+            _ => None,
         }
     }
 
@@ -158,13 +178,37 @@ impl Vfs {
             text,
             &db,
         )?);
-        let file = match self.0.ulock().entry(path) {
-            Entry::Occupied(_entry) => file,
-            Entry::Vacant(entry) => entry.insert(file).clone(),
-        };
+        // The file may already have been loaded from disk, in which case it is replaced
+        self.0.ulock().insert(path, file.clone());
         Ok(file)
     }
 
+    /// The texts of the Metamath database files opened in the editor, to be parsed instead
+    /// of the files on disk. They are given with the name the database uses for them,
+    /// among the provided file names.
+    pub fn edited_mm_texts(&self, file_names: &[String]) -> Vec<(String, Vec<u8>)> {
+        self.0
+            .ulock()
+            .iter()
+            .filter_map(|(path, file)| {
+                let name = file_names.iter().find(|name| path.is_named(name))?;
+                Some((name.clone(), file.edited_mm_text()?))
+            })
+            .collect()
+    }
+
+    /// The proof worksheets currently loaded
+    pub fn worksheets(&self) -> Vec<(FileRef, Arc<ProofWorksheet>)> {
+        self.0
+            .ulock()
+            .iter()
+            .filter_map(|(path, file)| match &file.contents.ulock().1 {
+                FileContents::MMPFile(worksheet) => Some((path.clone(), worksheet.clone())),
+                FileContents::MMFile(_) => None,
+            })
+            .collect()
+    }
+
     pub fn close(&self, path: &FileRef) {
         let mut g = self.0.ulock();
         g.remove(&path.clone());
@@ -0,0 +1,40 @@
+use crate::test_fixtures::{open_db, open_worksheet, TEST_DB};
+use crate::vfs::FileContents;
+use lsp_types::*;
+
+#[test]
+fn edited_mm_texts_named_like_the_database() {
+    let (path, vfs, _) = open_db("edited_texts.mm", TEST_DB);
+    let name = path.path().to_str().unwrap().to_string();
+    let texts = vfs.edited_mm_texts(&["other.mm".to_string(), name.clone()]);
+    assert_eq!(texts, vec![(name, TEST_DB.as_bytes().to_vec())]);
+    // Files which are not part of the database are not parsed
+    assert_eq!(vfs.edited_mm_texts(&["other.mm".to_string()]), vec![]);
+}
+
+#[test]
+fn worksheet_changed_while_used_by_a_request() {
+    let (_, vfs, db) = open_db("worksheet_change.mm", TEST_DB);
+    let path = open_worksheet(
+        "worksheet_change.mmp",
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+qed::ax-1      |- ( ph -> ( ps -> ph ) )
+",
+        &vfs,
+        &db,
+    );
+    // A request still holds the worksheet while the user edits it
+    let (_, held) = vfs.worksheets().pop().unwrap();
+    let change = TextDocumentContentChangeEvent {
+        range: Some(Range::new(Position::new(2, 0), Position::new(2, 0))),
+        range_length: None,
+        text: "1::ax-1        |- ( ps -> ( ph -> ps ) )\n".to_string(),
+    };
+    vfs.get(&path).unwrap().apply_change(2, &change);
+    assert_eq!(held.steps.len(), 1);
+    match vfs.source(path, &db).unwrap() {
+        FileContents::MMPFile(worksheet) => assert_eq!(worksheet.steps.len(), 2),
+        FileContents::MMFile(_) => panic!("Expected a proof worksheet"),
+    }
+}
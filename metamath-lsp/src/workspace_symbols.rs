@@ -0,0 +1,109 @@
+//! Provides workspace symbols, for searching labels in the whole database
+
+use crate::definition::SourceLocations;
+use crate::server::Cancellation;
+use crate::vfs::Vfs;
+use crate::ServerError;
+use lsp_types::*;
+use metamath_knife::outline::OutlineNodeRef;
+use metamath_knife::parser::HeadingLevel;
+use metamath_knife::statement::as_str;
+use metamath_knife::statement::StatementAddress;
+use metamath_knife::Database;
+use metamath_knife::StatementRef;
+use metamath_knife::StatementType;
+use std::collections::HashMap;
+
+/// Maximum number of symbols returned for a query
+const MAX_SYMBOLS: usize = 200;
+
+/// How well a label matches the query: the lower, the better.
+/// Returns `None` if the characters of the query do not appear in order in the label.
+/// The comparison is case insensitive.
+fn match_score(label: &str, query: &str) -> Option<u8> {
+    let label = label.to_ascii_lowercase();
+    if label == query {
+        Some(0)
+    } else if label.starts_with(query) {
+        Some(1)
+    } else if label.contains(query) {
+        Some(2)
+    } else {
+        let mut label_chars = label.chars();
+        query
+            .chars()
+            .all(|q| label_chars.any(|c| c == q))
+            .then(|| 3)
+    }
+}
+
+/// The symbol kind corresponding to a statement type
+fn symbol_kind(stmt: &StatementRef<'_>) -> Option<SymbolKind> {
+    match stmt.statement_type() {
+        StatementType::Provable => Some(SymbolKind::FUNCTION),
+        StatementType::Axiom if stmt.label().starts_with(b"df-") => Some(SymbolKind::CONSTRUCTOR),
+        StatementType::Axiom => Some(SymbolKind::INTERFACE),
+        StatementType::Essential | StatementType::Floating => Some(SymbolKind::FIELD),
+        _ => None,
+    }
+}
+
+/// Collects the names of the chapters and sections of the outline, by heading statement
+fn collect_headings(node: OutlineNodeRef, headings: &mut HashMap<StatementAddress, String>) {
+    for child in node.children_iter() {
+        if child.get_level() != HeadingLevel::Statement {
+            headings.insert(
+                child.get_statement().address(),
+                child.get_name().to_string(),
+            );
+            collect_headings(child, headings);
+        }
+    }
+}
+
+#[allow(deprecated)] // workaround rust#60681 - ironically, the field "deprecated" is deprecated.
+pub(crate) fn workspace_symbols(
+    query: String,
+    vfs: &Vfs,
+    db: Database,
+    cancel: &Cancellation,
+) -> Result<Vec<SymbolInformation>, ServerError> {
+    let query = query.to_ascii_lowercase();
+    if query.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut headings = HashMap::new();
+    collect_headings(OutlineNodeRef::root_node(&db), &mut headings);
+
+    // The statements are visited in database order, so that the section containing
+    // each statement is the last heading encountered.
+    let mut section = None;
+    let mut matches = vec![];
+    for stmt in db.statements() {
+        cancel.check()?;
+        if let Some(name) = headings.get(&stmt.address()) {
+            section = Some(name);
+        }
+        if let Some(kind) = symbol_kind(&stmt) {
+            if let Some(score) = match_score(as_str(stmt.label()), &query) {
+                matches.push((score, stmt, kind, section));
+            }
+        }
+    }
+    matches.sort_by_key(|(score, ..)| *score);
+    let mut sources = SourceLocations::new(vfs, &db);
+    Ok(matches
+        .into_iter()
+        .take(MAX_SYMBOLS)
+        .filter_map(|(_, stmt, kind, section)| {
+            Some(SymbolInformation {
+                name: as_str(stmt.label()).to_string(),
+                kind,
+                tags: None,
+                deprecated: None,
+                location: sources.location(stmt, stmt.span())?,
+                container_name: section.cloned(),
+            })
+        })
+        .collect())
+}
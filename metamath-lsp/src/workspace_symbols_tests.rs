@@ -0,0 +1,28 @@
+use crate::server::Cancellation;
+use crate::test_fixtures::{open_db, TEST_DB};
+use crate::workspace_symbols::workspace_symbols;
+use lsp_types::*;
+
+#[test]
+fn workspace_symbols_by_score() {
+    let (path, vfs, db) = open_db("workspace_symbols.mm", TEST_DB);
+    let cancel = Cancellation::default();
+    let symbols = workspace_symbols("A1I".to_string(), &vfs, db.clone(), &cancel).unwrap();
+    let symbols: Vec<_> = symbols
+        .iter()
+        .map(|symbol| (symbol.name.as_str(), symbol.kind))
+        .collect();
+    // The exact match first, then the labels starting with the query
+    assert_eq!(
+        symbols,
+        vec![("a1i", SymbolKind::FUNCTION), ("a1i.1", SymbolKind::FIELD)]
+    );
+    // Characters of the query may be apart, as long as they are in order
+    let symbols = workspace_symbols("amp".to_string(), &vfs, db.clone(), &cancel).unwrap();
+    let names: Vec<_> = symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+    assert_eq!(names, vec!["ax-mp"]);
+    assert_eq!(symbols[0].location.uri, *path.url());
+    assert!(workspace_symbols(String::new(), &vfs, db, &cancel)
+        .unwrap()
+        .is_empty());
+}
@@ -11,6 +11,7 @@ import {
 } from 'vscode-languageclient';
 import {
 	TextDocumentIdentifier,
+	WorkspaceEdit,
 } from 'vscode-languageserver-types';
 import {
 	TextDocumentPositionParams
@@ -27,12 +28,47 @@ interface ShowProofParams {
 	textDocument: TextDocumentIdentifier;
 	range: Range;
 	label: string;
+	worksheet?: boolean;
 }
 
 namespace ShowProofRequest {
 	export const type = new RequestType<ShowProofParams, string, void>('metamath/showProof');
 }
 
+interface UnifyParams {
+	textDocument: TextDocumentIdentifier;
+}
+
+namespace UnifyRequest {
+	export const type = new RequestType<UnifyParams, WorkspaceEdit | null, void>('metamath/unify');
+}
+
+interface GenerateProofParams {
+	textDocument: TextDocumentIdentifier;
+	format?: string;
+}
+
+namespace GenerateProofRequest {
+	export const type = new RequestType<GenerateProofParams, WorkspaceEdit | null, void>('metamath/generateProof');
+}
+
+interface StoreProofParams {
+	textDocument: TextDocumentIdentifier;
+	format?: string;
+}
+
+namespace StoreProofRequest {
+	export const type = new RequestType<StoreProofParams, WorkspaceEdit | null, void>('metamath/storeProof');
+}
+
+interface RenumberParams {
+	textDocument: TextDocumentIdentifier;
+}
+
+namespace RenumberRequest {
+	export const type = new RequestType<RenumberParams, WorkspaceEdit | null, void>('metamath/renumber');
+}
+
 namespace ToggleDvRequest {
 	export const type = new RequestType0<string, void>('metamath/toggleDv');
 }
@@ -48,7 +84,7 @@ function startClient() {
 
 	let mmLspPath: string = config.get('executablePath') || 'mm-lsp-server';
 	let mainFilePath: string = config.get('mainFilePath') || 'set.mm';
-	let jobs: string = config.get('jobs') || '1';
+	let jobs: string = String(config.get('jobs') || 1);
 
 	// If the extension is launched in debug mode then the debug server options are used
 	// Otherwise the run options are used
@@ -61,7 +97,9 @@ function startClient() {
 	let clientOptions: LanguageClientOptions = {
 		// Register the server for MM files
 		documentSelector: [{ scheme: 'file', language: 'metamath' }, { scheme: 'file', language: 'metamath-proof' }],
-		initializationOptions: { extraCapabilities: { goalView: true } }
+		initializationOptions: { extraCapabilities: { goalView: true } },
+		// Notify the server about changes to the Metamath settings
+		synchronize: { configurationSection: 'metamath' }
 	};
 
 	// Create the language client and start the client.
@@ -80,9 +118,20 @@ export function activate(context: ExtensionContext) {
 	context.subscriptions.push(
 		// For File search, see https://github.com/microsoft/vscode/issues/73524
 		// For Text search, see https://github.com/microsoft/vscode/issues/59921
-		commands.registerCommand('metamath.showProof', showProof),
+		commands.registerCommand('metamath.showProof', (label?: string) => showProof(label)),
+		commands.registerCommand('metamath.showProofAsWorksheet',
+			(label?: string) => showProof(label, true)),
+		commands.registerCommand('metamath.showReferences', showReferences),
+		commands.registerCommand('metamath.showLocation', showLocation),
 		commands.registerCommand('metamath.toggleDv', toggleDv),
 		commands.registerCommand('metamath.unify', unify),
+		commands.registerCommand('metamath.generateProof', () => generateProof()),
+		commands.registerCommand('metamath.generateProofAs',
+			() => pickProofFormat().then(format => format && generateProof(format))),
+		commands.registerCommand('metamath.storeProof', () => storeProof()),
+		commands.registerCommand('metamath.storeProofAs',
+			() => pickProofFormat().then(format => format && storeProof(format))),
+		commands.registerCommand('metamath.renumber', renumber),
 		commands.registerCommand('metamath.shutdownServer',
 			() => client.stop().then(() => {}, () => {})),
 		commands.registerCommand('metamath.restartServer',
@@ -106,14 +155,17 @@ function toggleDv() {
 	});
 }
 
-function showProof() {
+function showProof(label?: string, worksheet = false) {
 	const editor = window.activeTextEditor;
 	let selectionRange: Range;
 	if(!editor) {
 		return;
 	}
 
-	if (!editor.selection.isEmpty) {
+	if (label) {
+		// Label provided by a code lens
+		selectionRange = editor.selection;
+	} else if (!editor.selection.isEmpty) {
 		selectionRange = new Range(
 			editor.selection.start,
 			editor.selection.end
@@ -121,26 +173,121 @@ function showProof() {
 	} else {
 		selectionRange = editor.document.lineAt(editor.selection.start.line).range;
 	}
-	let label = editor.document.getText(selectionRange);
+	label = label || editor.document.getText(selectionRange);
 	let params: ShowProofParams = {
 		textDocument: TextDocumentIdentifier.create(editor.document.uri.toString()),
 		range: selectionRange,
-		label: label
+		label: label,
+		worksheet: worksheet
 	};
+	// Formulas rendered as Unicode text can not be parsed back, they are only shown as plain text
+	const unicode = !worksheet && workspace.getConfiguration('metamath').get<boolean>('unicode');
 	client.sendRequest(ShowProofRequest.type, params).then(async (content: any) => {
 		// Open a new document with the given MMP content
 		const doc = await workspace.openTextDocument({
-			language: 'metamath-proof',
+			language: unicode ? 'plaintext' : 'metamath-proof',
 			content: content
 		});
 		return await window.showTextDocument(doc);
 	});
 }
 
+function showReferences(uri: string, position: any) {
+	// Arguments provided by a code lens, in the LSP format
+	commands.executeCommand('editor.action.findReferences',
+		client.protocol2CodeConverter.asUri(uri),
+		client.protocol2CodeConverter.asPosition(position));
+}
+
+function showLocation(uri: string, range: any) {
+	// Arguments provided by a code lens, in the LSP format
+	window.showTextDocument(client.protocol2CodeConverter.asUri(uri), {
+		selection: client.protocol2CodeConverter.asRange(range)
+	});
+}
+
 function unify() {
-	// Display a message box to the user
-	window.showInformationMessage('Hello World from Metamath!');
-	//			editor.edit((editBuilder) => {
-	//				editBuilder.replace(range, result);
-	//			});
+	const editor = window.activeTextEditor;
+	if(!editor) {
+		return;
+	}
+
+	let params: UnifyParams = {
+		textDocument: TextDocumentIdentifier.create(editor.document.uri.toString()),
+	};
+	client.sendRequest(UnifyRequest.type, params).then(async (edit: WorkspaceEdit | null) => {
+		if (!edit) {
+			window.showInformationMessage('Nothing to unify.');
+			return;
+		}
+		// Apply the edits computed by the server to the proof file
+		await workspace.applyEdit(await client.protocol2CodeConverter.asWorkspaceEdit(edit));
+	});
+}
+
+// Lets the user choose the format of a proof, instead of the configured one
+function pickProofFormat(): Thenable<string | undefined> {
+	return window.showQuickPick(['compressed', 'normal', 'explicit'], {
+		placeHolder: 'Proof format',
+	});
+}
+
+function generateProof(format?: string) {
+	const editor = window.activeTextEditor;
+	if(!editor) {
+		return;
+	}
+
+	let params: GenerateProofParams = {
+		textDocument: TextDocumentIdentifier.create(editor.document.uri.toString()),
+		format: format,
+	};
+	client.sendRequest(GenerateProofRequest.type, params).then(async (edit: WorkspaceEdit | null) => {
+		if (!edit) {
+			return;
+		}
+		// Write the generated proof at the end of the proof file
+		await workspace.applyEdit(await client.protocol2CodeConverter.asWorkspaceEdit(edit));
+	}, (error: any) => {
+		window.showErrorMessage(`Could not generate proof: ${error.message}`);
+	});
+}
+
+function storeProof(format?: string) {
+	const editor = window.activeTextEditor;
+	if(!editor) {
+		return;
+	}
+
+	let params: StoreProofParams = {
+		textDocument: TextDocumentIdentifier.create(editor.document.uri.toString()),
+		format: format,
+	};
+	client.sendRequest(StoreProofRequest.type, params).then(async (edit: WorkspaceEdit | null) => {
+		if (!edit) {
+			return;
+		}
+		// Replace the theorem's proof in the database file
+		await workspace.applyEdit(await client.protocol2CodeConverter.asWorkspaceEdit(edit));
+	}, (error: any) => {
+		window.showErrorMessage(`Could not store proof: ${error.message}`);
+	});
+}
+
+function renumber() {
+	const editor = window.activeTextEditor;
+	if(!editor) {
+		return;
+	}
+
+	let params: RenumberParams = {
+		textDocument: TextDocumentIdentifier.create(editor.document.uri.toString()),
+	};
+	client.sendRequest(RenumberRequest.type, params).then(async (edit: WorkspaceEdit | null) => {
+		if (!edit) {
+			return;
+		}
+		// Rename the steps and the hypothesis references to them
+		await workspace.applyEdit(await client.protocol2CodeConverter.asWorkspaceEdit(edit));
+	});
 }
\ No newline at end of file